@@ -0,0 +1,375 @@
+use std::cmp::Ordering;
+
+use crate::op_code::Cond;
+use crate::trap::TrapKind;
+
+/*
+ * flag bits, set by every integer op in the flags byte at FLAGS_ADDR
+ *
+ * ZERO         the result is 0
+ * CARRY        unsigned overflow: carry out of ADD, borrow from SUB,
+ *              MUL result too big, or the last bit shifted out
+ * OVERFLOW     signed overflow: ADD, SUB, MUL result too big or MIN / -1
+ * NEGATIVE     the top bit of the result is set
+ */
+pub const ZERO: u8 = 1;
+pub const CARRY: u8 = 1 << 1;
+pub const OVERFLOW: u8 = 1 << 2;
+pub const NEGATIVE: u8 = 1 << 3;
+
+/**
+ * Integer operations, each is a family of op codes which differ by the
+ * width of the operands and whether the source is a value or pointer.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntOp {
+    Add, Sub, Mul, Div, Mod,
+    Shr, Shl,
+    And, Orr, Xor,
+    Dvs, Mds, Sar,
+}
+
+fn mask(width: usize) -> u64 {
+    if width >= 8 {
+        return u64::MAX;
+    }
+
+    return (1 << (8 * width)) - 1;
+}
+
+/**
+ * sign extend a width byte value
+ */
+fn sext(x: u64, width: usize) -> i64 {
+    let shift = 64 - 8 * width as u32;
+    return ((x << shift) as i64) >> shift;
+}
+
+fn sign(x: u64, width: usize) -> bool {
+    return (x >> (8 * width - 1)) & 1 == 1;
+}
+
+/**
+ * Evaluate dst op src on width byte operands, which are zero extended to
+ * 64 bits. Results wrap to the width, returns the result and the flags
+ * it sets.
+ */
+pub fn eval(op: IntOp, width: usize, dst: u64, src: u64)
+    -> Result<(u64, u8), TrapKind> {
+
+    let bits = 8 * width as u64;
+    let mut flags = 0;
+
+    let res = match op {
+        IntOp::Add => {
+            let full = dst as u128 + src as u128;
+            let res = full as u64 & mask(width);
+            if full >> bits != 0 {
+                flags |= CARRY;
+            }
+            if sign(dst, width) == sign(src, width)
+                && sign(res, width) != sign(dst, width) {
+                flags |= OVERFLOW;
+            }
+            res
+        },
+        IntOp::Sub => {
+            let res = dst.wrapping_sub(src) & mask(width);
+            if dst < src {
+                flags |= CARRY;
+            }
+            if sign(dst, width) != sign(src, width)
+                && sign(res, width) != sign(dst, width) {
+                flags |= OVERFLOW;
+            }
+            res
+        },
+        IntOp::Mul => {
+            let full = dst as u128 * src as u128;
+            let res = full as u64 & mask(width);
+            if full >> bits != 0 {
+                flags |= CARRY;
+            }
+            let signed = sext(dst, width) as i128 * sext(src, width) as i128;
+            if signed != sext(res, width) as i128 {
+                flags |= OVERFLOW;
+            }
+            res
+        },
+        IntOp::Div | IntOp::Mod | IntOp::Dvs | IntOp::Mds if src == 0 => {
+            return Err(TrapKind::DivideByZero);
+        },
+        IntOp::Div => dst / src,
+        IntOp::Mod => dst % src,
+        IntOp::Dvs => {
+            let (a, b) = (sext(dst, width), sext(src, width));
+            // MIN / -1 is the only division which can overflow
+            if a == sext(1 << (bits - 1), width) && b == -1 {
+                flags |= OVERFLOW;
+                dst
+            } else {
+                (a / b) as u64 & mask(width)
+            }
+        },
+        IntOp::Mds => {
+            let (a, b) = (sext(dst, width), sext(src, width));
+            a.wrapping_rem(b) as u64 & mask(width)
+        },
+
+        // shifting by the width or more shifts everything out
+        IntOp::Shr | IntOp::Shl | IntOp::Sar if src == 0 => dst,
+        IntOp::Shr => {
+            if src <= bits && (dst >> (src - 1)) & 1 == 1 {
+                flags |= CARRY;
+            }
+            if src >= bits { 0 } else { dst >> src }
+        },
+        IntOp::Shl => {
+            if src <= bits && (dst >> (bits - src)) & 1 == 1 {
+                flags |= CARRY;
+            }
+            if src >= bits { 0 } else { (dst << src) & mask(width) }
+        },
+        IntOp::Sar => {
+            let last = if src >= bits { bits - 1 } else { src - 1 };
+            if (dst >> last) & 1 == 1 {
+                flags |= CARRY;
+            }
+            (sext(dst, width) >> src.min(bits - 1)) as u64 & mask(width)
+        },
+
+        IntOp::And => dst & src,
+        IntOp::Orr => dst | src,
+        IntOp::Xor => dst ^ src,
+    };
+
+    if res == 0 {
+        flags |= ZERO;
+    }
+    if sign(res, width) {
+        flags |= NEGATIVE;
+    }
+
+    return Ok((res, flags));
+}
+
+/**
+ * Floating point operations, each is a family of op codes which differ by
+ * precision and whether the source is a value or pointer.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatOp {
+    Add, Sub, Mul, Div,
+    Cmp,
+}
+
+/**
+ * Evaluate dst op src. f32 operands are evaluated as f64 and rounded back,
+ * which is exact since f64 has more than twice the precision of f32.
+ *
+ * Cmp leaves dst as is and returns the flags it sets: ZERO if they are
+ * equal, CARRY and NEGATIVE if dst is less than src and OVERFLOW if they
+ * are unordered, meaning one is NaN.
+ */
+pub fn eval_float(op: FloatOp, dst: f64, src: f64) -> (f64, Option<u8>) {
+    match op {
+        FloatOp::Add => return (dst + src, None),
+        FloatOp::Sub => return (dst - src, None),
+        FloatOp::Mul => return (dst * src, None),
+        FloatOp::Div => return (dst / src, None),
+        FloatOp::Cmp => {},
+    }
+
+    let flags = match dst.partial_cmp(&src) {
+        Some(Ordering::Equal) => ZERO,
+        Some(Ordering::Less) => CARRY | NEGATIVE,
+        Some(Ordering::Greater) => 0,
+        None => OVERFLOW,
+    };
+
+    return (dst, Some(flags));
+}
+
+/**
+ * Compare width byte integers, zero extended to 64 bits
+ */
+pub fn compare(cond: Cond, width: usize, a: u64, b: u64) -> bool {
+    let (sa, sb) = (sext(a, width), sext(b, width));
+
+    match cond {
+        Cond::EQ => return a == b,
+        Cond::NE => return a != b,
+        Cond::LT => return a < b,
+        Cond::LE => return a <= b,
+        Cond::GT => return a > b,
+        Cond::GE => return a >= b,
+        Cond::LTS => return sa < sb,
+        Cond::LES => return sa <= sb,
+        Cond::GTS => return sa > sb,
+        Cond::GES => return sa >= sb,
+    }
+}
+
+/**
+ * Compare floats, every condition but NE is false if either is NaN
+ */
+pub fn compare_float(cond: Cond, a: f64, b: f64) -> bool {
+    match cond {
+        Cond::EQ => return a == b,
+        Cond::NE => return a != b,
+        Cond::LT | Cond::LTS => return a < b,
+        Cond::LE | Cond::LES => return a <= b,
+        Cond::GT | Cond::GTS => return a > b,
+        Cond::GE | Cond::GES => return a >= b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTHS: [usize; 4] = [1, 2, 4, 8];
+
+    /**
+     * the largest unsigned and smallest signed value of a width
+     */
+    fn limits(width: usize) -> (u64, u64) {
+        return (mask(width), 1 << (8 * width - 1));
+    }
+
+    #[test]
+    fn add_flags() {
+        for &w in &WIDTHS {
+            let (max, min) = limits(w);
+            assert_eq!(eval(IntOp::Add, w, 1, 2), Ok((3, 0)), "width {}", w);
+            assert_eq!(eval(IntOp::Add, w, max, 1), Ok((0, ZERO | CARRY)), "width {}", w);
+            assert_eq!(eval(IntOp::Add, w, min - 1, 1),
+                       Ok((min, OVERFLOW | NEGATIVE)), "width {}", w);
+            assert_eq!(eval(IntOp::Add, w, min, min),
+                       Ok((0, ZERO | CARRY | OVERFLOW)), "width {}", w);
+        }
+    }
+
+    #[test]
+    fn sub_flags() {
+        for &w in &WIDTHS {
+            let (max, min) = limits(w);
+            assert_eq!(eval(IntOp::Sub, w, 5, 5), Ok((0, ZERO)), "width {}", w);
+            assert_eq!(eval(IntOp::Sub, w, 0, 1),
+                       Ok((max, CARRY | NEGATIVE)), "width {}", w);
+            assert_eq!(eval(IntOp::Sub, w, min, 1),
+                       Ok((min - 1, OVERFLOW)), "width {}", w);
+            assert_eq!(eval(IntOp::Sub, w, 0, min),
+                       Ok((min, CARRY | OVERFLOW | NEGATIVE)), "width {}", w);
+        }
+    }
+
+    #[test]
+    fn mul_flags() {
+        for &w in &WIDTHS {
+            let (max, min) = limits(w);
+            assert_eq!(eval(IntOp::Mul, w, 6, 7), Ok((42, 0)), "width {}", w);
+            assert_eq!(eval(IntOp::Mul, w, min, 2),
+                       Ok((0, ZERO | CARRY | OVERFLOW)), "width {}", w);
+            // -1 * -1 only overflows unsigned
+            assert_eq!(eval(IntOp::Mul, w, max, max), Ok((1, CARRY)), "width {}", w);
+            assert_eq!(eval(IntOp::Mul, w, min >> 1, 2),
+                       Ok((min, OVERFLOW | NEGATIVE)), "width {}", w);
+        }
+    }
+
+    #[test]
+    fn division() {
+        for &w in &WIDTHS {
+            let (max, min) = limits(w);
+            let neg = |x: i64| x as u64 & mask(w);
+
+            for &op in &[IntOp::Div, IntOp::Mod, IntOp::Dvs, IntOp::Mds] {
+                assert_eq!(eval(op, w, 1, 0), Err(TrapKind::DivideByZero));
+            }
+
+            assert_eq!(eval(IntOp::Div, w, max, 2), Ok((max >> 1, 0)), "width {}", w);
+            assert_eq!(eval(IntOp::Mod, w, 7, 7), Ok((0, ZERO)), "width {}", w);
+            assert_eq!(eval(IntOp::Dvs, w, neg(-7), 2),
+                       Ok((neg(-3), NEGATIVE)), "width {}", w);
+            assert_eq!(eval(IntOp::Mds, w, neg(-7), 2),
+                       Ok((max, NEGATIVE)), "width {}", w);
+
+            // MIN / -1 overflows, MIN % -1 is 0
+            assert_eq!(eval(IntOp::Dvs, w, min, max),
+                       Ok((min, OVERFLOW | NEGATIVE)), "width {}", w);
+            assert_eq!(eval(IntOp::Mds, w, min, max), Ok((0, ZERO)), "width {}", w);
+        }
+    }
+
+    #[test]
+    fn shifts() {
+        for &w in &WIDTHS {
+            let (max, min) = limits(w);
+            let bits = 8 * w as u64;
+
+            // by 0 leaves the value and only sets Z and N
+            for &op in &[IntOp::Shr, IntOp::Shl, IntOp::Sar] {
+                assert_eq!(eval(op, w, min, 0), Ok((min, NEGATIVE)), "width {}", w);
+            }
+
+            assert_eq!(eval(IntOp::Shr, w, 3, 1), Ok((1, CARRY)), "width {}", w);
+            assert_eq!(eval(IntOp::Shr, w, min, bits),
+                       Ok((0, ZERO | CARRY)), "width {}", w);
+            assert_eq!(eval(IntOp::Shr, w, min, bits + 1), Ok((0, ZERO)), "width {}", w);
+
+            assert_eq!(eval(IntOp::Shl, w, min, 1),
+                       Ok((0, ZERO | CARRY)), "width {}", w);
+            assert_eq!(eval(IntOp::Shl, w, 1, bits - 1),
+                       Ok((min, NEGATIVE)), "width {}", w);
+            assert_eq!(eval(IntOp::Shl, w, 1, bits), Ok((0, ZERO | CARRY)), "width {}", w);
+            assert_eq!(eval(IntOp::Shl, w, 1, bits + 1), Ok((0, ZERO)), "width {}", w);
+
+            assert_eq!(eval(IntOp::Sar, w, min, 1),
+                       Ok((min | min >> 1, NEGATIVE)), "width {}", w);
+            assert_eq!(eval(IntOp::Sar, w, 1, 1), Ok((0, ZERO | CARRY)), "width {}", w);
+            assert_eq!(eval(IntOp::Sar, w, min, bits),
+                       Ok((max, CARRY | NEGATIVE)), "width {}", w);
+            assert_eq!(eval(IntOp::Sar, w, min, 1000),
+                       Ok((max, CARRY | NEGATIVE)), "width {}", w);
+        }
+    }
+
+    #[test]
+    fn bitwise() {
+        for &w in &WIDTHS {
+            let (max, min) = limits(w);
+            assert_eq!(eval(IntOp::And, w, max, 0), Ok((0, ZERO)), "width {}", w);
+            assert_eq!(eval(IntOp::Orr, w, min, 1),
+                       Ok((min | 1, NEGATIVE)), "width {}", w);
+            assert_eq!(eval(IntOp::Xor, w, max, max), Ok((0, ZERO)), "width {}", w);
+        }
+    }
+
+    #[test]
+    fn sign_extension() {
+        for &w in &WIDTHS {
+            let (max, min) = limits(w);
+            assert_eq!(sext(max, w), -1, "width {}", w);
+            assert_eq!(sext(min - 1, w), (min - 1) as i64, "width {}", w);
+            assert!(sext(min, w) < 0, "width {}", w);
+
+            assert!(compare(Cond::LTS, w, min, 0), "width {}", w);
+            assert!(!compare(Cond::LT, w, min, 0), "width {}", w);
+            assert!(compare(Cond::GES, w, 0, max), "width {}", w);
+            assert!(compare(Cond::GE, w, max, 0), "width {}", w);
+        }
+    }
+
+    #[test]
+    fn float_compare_flags() {
+        assert_eq!(eval_float(FloatOp::Cmp, 1.0, 1.0), (1.0, Some(ZERO)));
+        assert_eq!(eval_float(FloatOp::Cmp, 1.0, 2.0), (1.0, Some(CARRY | NEGATIVE)));
+        assert_eq!(eval_float(FloatOp::Cmp, 2.0, 1.0), (2.0, Some(0)));
+        assert_eq!(eval_float(FloatOp::Cmp, f64::NAN, 1.0).1, Some(OVERFLOW));
+
+        assert!(compare_float(Cond::NE, f64::NAN, f64::NAN));
+        assert!(!compare_float(Cond::EQ, f64::NAN, f64::NAN));
+        assert!(!compare_float(Cond::GE, f64::NAN, 1.0));
+    }
+}
@@ -0,0 +1,1086 @@
+/* .label
+ *      directive "mem static" ; directive gets
+ *                             ; treated as a command all the way
+ *                             ; to the parser
+ * ._SECTION_LABEL_
+ * .label1 ; comment
+ *      COMMAND 1 2
+ *      command one two ; comments
+ * .label2
+ *      command
+ */
+/*
+ * after parsing the file, the assembler compiles a binary file
+ * by:
+ *      -read nodes one by one put labels in a map
+ */
+
+
+use std::collections::HashMap;
+
+use crate::alu;
+use crate::op_code::{Cond, Op, OpExt, Operand};
+use crate::memory;
+use crate::ast;
+use crate::ast::{AddrExpr, Value};
+use crate::ast::AstNode;
+
+/**
+ * Assemble mvm assembly source into a program which can be loaded with
+ * `Vm::new`.
+ */
+pub fn assemble(src: &str) -> Result<Vec<u8>, String> {
+    let root = ast::parse(src.to_string())?;
+    return compile(&root);
+}
+
+/**
+ * Like `assemble`, but also returns the address of each label
+ */
+pub fn assemble_with_labels(src: &str)
+    -> Result<(Vec<u8>, HashMap<String, usize>), String> {
+
+    let root = ast::parse(src.to_string())?;
+    return compile_with_labels(&root);
+}
+
+pub fn compile(root:&AstNode) -> Result<Vec<u8>, String> {
+    let (code, _) = compile_with_labels(root)?;
+    return Ok(code);
+}
+
+pub fn compile_with_labels(root:&AstNode)
+    -> Result<(Vec<u8>, HashMap<String, usize>), String> {
+
+    let mut labels: HashMap<String, usize> = HashMap::new();
+
+    // builtin labels
+    labels.insert("._zero".to_string(), memory::PROG_OFFSET);
+    labels.insert("._flags".to_string(), memory::FLAGS_ADDR);
+
+    let nodes: &Vec<AstNode>;
+    if let AstNode::Tree(_, nodes1) = root {
+        nodes = nodes1;
+    } else {
+        panic!("root is not AstNode::Tree, got {:?}", root)
+    }
+
+    // the 0 byte is for an exit code and the 1 byte for flags
+    let mut prog_size = memory::CODE_OFFSET - memory::PROG_OFFSET;
+
+    // fill labels
+    for node in nodes {
+        match node {
+            AstNode::Cmd(cmd, args) => {
+                prog_size += AsmCmd::size_from_string(cmd, args)?;
+            },
+            AstNode::Label(name) => {
+                match labels.get(name) {
+                    Some(_) => {
+                        return Err(format!("label already defined {:?}", node))
+                    },
+                    None => {
+                        labels.insert(name.to_string(),
+                            prog_size + memory::PROG_OFFSET);
+                    },
+                };
+            },
+            _ => {}, // ignore comments
+        }
+    }
+
+    // returned vector
+    let mut ret = Vec::with_capacity(prog_size);
+
+    // actually compile the program
+    for node in nodes {
+        // labels and comments do not output anything
+        if let AstNode::Cmd(cmd, args) = node {
+            let byt = cmd.parse::<AsmCmd>()?.compile(args, &labels)?;
+            ret.extend(byt.iter());
+        }
+    }
+
+    return Ok((ret, labels))
+}
+
+/**
+ * Symbol files list one label and its address per line, ordered by
+ * address, so tools like the debugger can refer to labels.
+ */
+pub fn write_symbols(labels: &HashMap<String, usize>) -> String {
+    let mut syms: Vec<(&String, &usize)> = labels.iter().collect();
+    syms.sort_by_key(|&(name, addr)| (*addr, name.clone()));
+
+    let mut ret = String::new();
+    for (name, addr) in syms {
+        ret.push_str(&format!("{} {}\n", name, addr));
+    }
+
+    return ret;
+}
+
+pub fn parse_symbols(src: &str) -> Result<HashMap<String, usize>, String> {
+    let mut labels = HashMap::new();
+
+    for line in src.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next().map(str::parse::<usize>), fields.next()) {
+            (Some(name), Some(Ok(addr)), None) => {
+                labels.insert(name.to_string(), addr);
+            },
+            _ => return Err(format!("bad symbol line {:?}", line)),
+        }
+    }
+
+    return Ok(labels);
+}
+
+/*
+ * Assembler commands. These relate to op codes in the VM, but the op
+ * codes are picked implicitly based on the arguments.
+ */
+dense_enum! { AsmCmd;
+    NOP, XIT,
+
+    // byte
+    ADDB, SUBB, MULB, DIVB, MODB,
+    SHRB, SHLB,
+    ANDB, ORRB, XORB,
+    DIVSB, MODSB, SARB,
+
+    // word
+    ADDW, SUBW, MULW, DIVW, MODW,
+    SHRW, SHLW,
+    ANDW, ORRW, XORW,
+    DIVSW, MODSW, SARW,
+
+    // half word, 2 bytes
+    ADDH, SUBH, MULH, DIVH, MODH,
+    SHRH, SHLH,
+    ANDH, ORRH, XORH,
+    DIVSH, MODSH, SARH,
+
+    // double word, 4 bytes
+    ADDD, SUBD, MULD, DIVD, MODD,
+    SHRD, SHLD,
+    ANDD, ORRD, XORD,
+    DIVSD, MODSD, SARD,
+
+    // f32 and f64 floats, CMPF sets flags like a subtraction would
+    ADDF, SUBF, MULF, DIVF, CMPF,
+    ADDFW, SUBFW, MULFW, DIVFW, CMPFW,
+
+    // comparisons writing 1 or 0 to dst, S compares as signed
+    EQB, NEB, LTB, LEB, GTB, GEB,
+    LTSB, LESB, GTSB, GESB,
+    EQW, NEW, LTW, LEW, GTW, GEW,
+    LTSW, LESW, GTSW, GESW,
+    EQH, NEH, LTH, LEH, GTH, GEH,
+    LTSH, LESH, GTSH, GESH,
+    EQD, NED, LTD, LED, GTD, GED,
+    LTSD, LESD, GTSD, GESD,
+    EQF, NEF, LTF, LEF, GTF, GEF,
+    EQFW, NEFW, LTFW, LEFW, GTFW, GEFW,
+
+    // conversions between words and floats, and between float widths
+    ITF, ITFW,
+    TRNF, TRNFW,
+    RNDF, RNDFW,
+    EXTF, NRWF,
+
+    CPYB, CPYW, CPYH, CPYD,
+
+    // block memory, the len is a number or where it is stored
+    MCPY, MSET, MCMP,
+
+    JMP, JIT, CAL, RET,
+
+    // jump on the flags set by the last integer op
+    JZ, JNZ, JC, JNC,
+    JV, JNV, JN, JNN,
+
+    PSHB, POPB,
+    PSHW, POPW,
+    PSHH, POPH,
+    PSHD, POPD,
+
+    SYS,
+
+    // extension codes
+    SLP,
+    APG, FPG,
+//    ASY,
+    CMT,
+    ALLOC, FREE,
+
+    // STTC, // values in binary
+    // ALLO, // values requested
+}
+
+impl AsmCmd {
+
+    fn base_op_code(&self) -> u8 {
+        return match *self {
+            AsmCmd::ADDB | AsmCmd::ADDW |
+            AsmCmd::ADDH | AsmCmd::ADDD => Op::ADD1,
+            AsmCmd::SUBB | AsmCmd::SUBW |
+            AsmCmd::SUBH | AsmCmd::SUBD => Op::SUB1,
+            AsmCmd::MULB | AsmCmd::MULW |
+            AsmCmd::MULH | AsmCmd::MULD => Op::MUL1,
+            AsmCmd::DIVB | AsmCmd::DIVW |
+            AsmCmd::DIVH | AsmCmd::DIVD => Op::DIV1,
+            AsmCmd::MODB | AsmCmd::MODW |
+            AsmCmd::MODH | AsmCmd::MODD => Op::MOD1,
+            AsmCmd::SHRB | AsmCmd::SHRW |
+            AsmCmd::SHRH | AsmCmd::SHRD => Op::SHR1,
+            AsmCmd::SHLB | AsmCmd::SHLW |
+            AsmCmd::SHLH | AsmCmd::SHLD => Op::SHL1,
+            AsmCmd::ANDB | AsmCmd::ANDW |
+            AsmCmd::ANDH | AsmCmd::ANDD => Op::AND1,
+            AsmCmd::ORRB | AsmCmd::ORRW |
+            AsmCmd::ORRH | AsmCmd::ORRD => Op::ORR1,
+            AsmCmd::XORB | AsmCmd::XORW |
+            AsmCmd::XORH | AsmCmd::XORD => Op::XOR1,
+            AsmCmd::DIVSB | AsmCmd::DIVSW |
+            AsmCmd::DIVSH | AsmCmd::DIVSD => Op::DVS1,
+            AsmCmd::MODSB | AsmCmd::MODSW |
+            AsmCmd::MODSH | AsmCmd::MODSD => Op::MDS1,
+            AsmCmd::SARB | AsmCmd::SARW |
+            AsmCmd::SARH | AsmCmd::SARD => Op::SAR1,
+            AsmCmd::CPYB | AsmCmd::CPYW |
+            AsmCmd::CPYH | AsmCmd::CPYD => Op::CPY1,
+            AsmCmd::PSHB | AsmCmd::PSHW |
+            AsmCmd::PSHH | AsmCmd::PSHD => Op::PSH1,
+            AsmCmd::ADDF | AsmCmd::ADDFW => Op::ADF1,
+            AsmCmd::SUBF | AsmCmd::SUBFW => Op::SBF1,
+            AsmCmd::MULF | AsmCmd::MULFW => Op::MLF1,
+            AsmCmd::DIVF | AsmCmd::DIVFW => Op::DVF1,
+            AsmCmd::CMPF | AsmCmd::CMPFW => Op::CMF1,
+            AsmCmd::EQB | AsmCmd::NEB | AsmCmd::LTB | AsmCmd::LEB | AsmCmd::GTB |
+            AsmCmd::GEB | AsmCmd::LTSB | AsmCmd::LESB | AsmCmd::GTSB | AsmCmd::GESB |
+            AsmCmd::EQW | AsmCmd::NEW | AsmCmd::LTW | AsmCmd::LEW | AsmCmd::GTW |
+            AsmCmd::GEW | AsmCmd::LTSW | AsmCmd::LESW | AsmCmd::GTSW | AsmCmd::GESW |
+            AsmCmd::EQH | AsmCmd::NEH | AsmCmd::LTH | AsmCmd::LEH | AsmCmd::GTH |
+            AsmCmd::GEH | AsmCmd::LTSH | AsmCmd::LESH | AsmCmd::GTSH | AsmCmd::GESH |
+            AsmCmd::EQD | AsmCmd::NED | AsmCmd::LTD | AsmCmd::LED | AsmCmd::GTD |
+            AsmCmd::GED | AsmCmd::LTSD | AsmCmd::LESD | AsmCmd::GTSD | AsmCmd::GESD => Op::CPI1,
+            AsmCmd::EQF | AsmCmd::NEF | AsmCmd::LTF | AsmCmd::LEF | AsmCmd::GTF |
+            AsmCmd::GEF | AsmCmd::EQFW | AsmCmd::NEFW | AsmCmd::LTFW | AsmCmd::LEFW |
+            AsmCmd::GTFW | AsmCmd::GEFW => Op::CPF1,
+            AsmCmd::POPB | AsmCmd::POPW |
+            AsmCmd::POPH | AsmCmd::POPD => Op::POP1,
+            _ => panic!("no base op for {}", self),
+        } as u8
+    }
+
+    /**
+     * offset based on size of the data being operated
+     */
+    fn base_op_offset(&self) -> u8 {
+        match self {
+            // byte
+            AsmCmd::ADDB | AsmCmd::SUBB | AsmCmd::MULB | AsmCmd::DIVB | AsmCmd::MODB |
+            AsmCmd::SHRB | AsmCmd::SHLB |
+            AsmCmd::ANDB | AsmCmd::ORRB | AsmCmd::XORB |
+            AsmCmd::DIVSB | AsmCmd::MODSB | AsmCmd::SARB |
+            AsmCmd::CPYB |
+            AsmCmd::PSHB | AsmCmd::POPB |
+            AsmCmd::ADDF | AsmCmd::SUBF | AsmCmd::MULF | AsmCmd::DIVF |
+            AsmCmd::CMPF |
+            AsmCmd::EQB | AsmCmd::NEB | AsmCmd::LTB | AsmCmd::LEB | AsmCmd::GTB |
+            AsmCmd::GEB | AsmCmd::LTSB | AsmCmd::LESB | AsmCmd::GTSB | AsmCmd::GESB |
+            AsmCmd::EQF | AsmCmd::NEF | AsmCmd::LTF | AsmCmd::LEF | AsmCmd::GTF |
+            AsmCmd::GEF => 0,
+
+            // word
+            AsmCmd::ADDW | AsmCmd::SUBW | AsmCmd::MULW | AsmCmd::DIVW | AsmCmd::MODW |
+            AsmCmd::SHRW | AsmCmd::SHLW |
+            AsmCmd::ANDW | AsmCmd::ORRW | AsmCmd::XORW |
+            AsmCmd::DIVSW | AsmCmd::MODSW | AsmCmd::SARW |
+            AsmCmd::CPYW |
+            AsmCmd::PSHW | AsmCmd::POPW |
+            AsmCmd::ADDFW | AsmCmd::SUBFW | AsmCmd::MULFW | AsmCmd::DIVFW |
+            AsmCmd::CMPFW |
+            AsmCmd::EQW | AsmCmd::NEW | AsmCmd::LTW | AsmCmd::LEW | AsmCmd::GTW |
+            AsmCmd::GEW | AsmCmd::LTSW | AsmCmd::LESW | AsmCmd::GTSW | AsmCmd::GESW |
+            AsmCmd::EQFW | AsmCmd::NEFW | AsmCmd::LTFW | AsmCmd::LEFW | AsmCmd::GTFW |
+            AsmCmd::GEFW => 2,
+
+            // half word
+            AsmCmd::ADDH | AsmCmd::SUBH |
+            AsmCmd::MULH | AsmCmd::DIVH | AsmCmd::MODH |
+            AsmCmd::SHRH | AsmCmd::SHLH |
+            AsmCmd::ANDH | AsmCmd::ORRH | AsmCmd::XORH |
+            AsmCmd::DIVSH | AsmCmd::MODSH | AsmCmd::SARH |
+            AsmCmd::CPYH |
+            AsmCmd::PSHH | AsmCmd::POPH |
+            AsmCmd::EQH | AsmCmd::NEH | AsmCmd::LTH | AsmCmd::LEH | AsmCmd::GTH |
+            AsmCmd::GEH | AsmCmd::LTSH | AsmCmd::LESH | AsmCmd::GTSH | AsmCmd::GESH => 4,
+
+            // double word
+            AsmCmd::ADDD | AsmCmd::SUBD |
+            AsmCmd::MULD | AsmCmd::DIVD | AsmCmd::MODD |
+            AsmCmd::SHRD | AsmCmd::SHLD |
+            AsmCmd::ANDD | AsmCmd::ORRD | AsmCmd::XORD |
+            AsmCmd::DIVSD | AsmCmd::MODSD | AsmCmd::SARD |
+            AsmCmd::CPYD |
+            AsmCmd::PSHD | AsmCmd::POPD |
+            AsmCmd::EQD | AsmCmd::NED | AsmCmd::LTD | AsmCmd::LED | AsmCmd::GTD |
+            AsmCmd::GED | AsmCmd::LTSD | AsmCmd::LESD | AsmCmd::GTSD | AsmCmd::GESD => 6,
+
+            _ => panic!("no offset for {}", self)
+        }
+    }
+
+    /**
+     * width in bytes of the data being operated
+     */
+    fn width(&self) -> usize {
+        if let AsmCmd::ADDF | AsmCmd::SUBF | AsmCmd::MULF | AsmCmd::DIVF |
+            AsmCmd::CMPF |
+            AsmCmd::EQF | AsmCmd::NEF | AsmCmd::LTF | AsmCmd::LEF | AsmCmd::GTF |
+            AsmCmd::GEF = self {
+            return 4;
+        }
+
+        match self.base_op_offset() {
+            0 => 1,
+            2 => 8,
+            4 => 2,
+            _ => 4,
+        }
+    }
+
+    /**
+     * condition of a comparison
+     */
+    fn cond(&self) -> Cond {
+        match self {
+            AsmCmd::EQB | AsmCmd::EQW | AsmCmd::EQH | AsmCmd::EQD |
+            AsmCmd::EQF | AsmCmd::EQFW => Cond::EQ,
+            AsmCmd::NEB | AsmCmd::NEW | AsmCmd::NEH | AsmCmd::NED |
+            AsmCmd::NEF | AsmCmd::NEFW => Cond::NE,
+            AsmCmd::LTB | AsmCmd::LTW | AsmCmd::LTH | AsmCmd::LTD |
+            AsmCmd::LTF | AsmCmd::LTFW => Cond::LT,
+            AsmCmd::LEB | AsmCmd::LEW | AsmCmd::LEH | AsmCmd::LED |
+            AsmCmd::LEF | AsmCmd::LEFW => Cond::LE,
+            AsmCmd::GTB | AsmCmd::GTW | AsmCmd::GTH | AsmCmd::GTD |
+            AsmCmd::GTF | AsmCmd::GTFW => Cond::GT,
+            AsmCmd::GEB | AsmCmd::GEW | AsmCmd::GEH | AsmCmd::GED |
+            AsmCmd::GEF | AsmCmd::GEFW => Cond::GE,
+            AsmCmd::LTSB | AsmCmd::LTSW | AsmCmd::LTSH | AsmCmd::LTSD => Cond::LTS,
+            AsmCmd::LESB | AsmCmd::LESW | AsmCmd::LESH | AsmCmd::LESD => Cond::LES,
+            AsmCmd::GTSB | AsmCmd::GTSW | AsmCmd::GTSH | AsmCmd::GTSD => Cond::GTS,
+            AsmCmd::GESB | AsmCmd::GESW | AsmCmd::GESH | AsmCmd::GESD => Cond::GES,
+            _ => panic!("no condition for {}", self),
+        }
+    }
+
+    fn size_from_string(cmd: &str, args: &Vec<Value>)
+        -> Result<usize, String> {
+
+        let cmd1 = cmd.parse::<AsmCmd>()?;
+
+        let ret = match cmd1 {
+            AsmCmd::NOP | AsmCmd::XIT | AsmCmd::RET=> 1, // no arg
+            AsmCmd::JMP | AsmCmd::CAL => 1 + 8, // op code and jump address
+            AsmCmd::JIT => 1 + 8 + 8, // op code jump address, boolean address
+            AsmCmd::JZ | AsmCmd::JNZ | AsmCmd::JC | AsmCmd::JNC |
+            AsmCmd::JV | AsmCmd::JNV | AsmCmd::JN | AsmCmd::JNN => {
+                1 + 8 + 1 // op code, jump address, flag mask
+            },
+
+            AsmCmd::ADDB | AsmCmd::SUBB |
+            AsmCmd::MULB | AsmCmd::DIVB | AsmCmd::MODB |
+            AsmCmd::SHRB | AsmCmd::SHLB |
+            AsmCmd::ANDB | AsmCmd::ORRB | AsmCmd::XORB |
+            AsmCmd::DIVSB | AsmCmd::MODSB | AsmCmd::SARB |
+            AsmCmd::CPYB |
+            AsmCmd::ADDW | AsmCmd::SUBW |
+            AsmCmd::MULW | AsmCmd::DIVW | AsmCmd::MODW |
+            AsmCmd::SHRW | AsmCmd::SHLW |
+            AsmCmd::ANDW | AsmCmd::ORRW | AsmCmd::XORW |
+            AsmCmd::DIVSW | AsmCmd::MODSW | AsmCmd::SARW |
+            AsmCmd::CPYW |
+            AsmCmd::ADDH | AsmCmd::SUBH |
+            AsmCmd::MULH | AsmCmd::DIVH | AsmCmd::MODH |
+            AsmCmd::SHRH | AsmCmd::SHLH |
+            AsmCmd::ANDH | AsmCmd::ORRH | AsmCmd::XORH |
+            AsmCmd::DIVSH | AsmCmd::MODSH | AsmCmd::SARH |
+            AsmCmd::CPYH |
+            AsmCmd::ADDD | AsmCmd::SUBD |
+            AsmCmd::MULD | AsmCmd::DIVD | AsmCmd::MODD |
+            AsmCmd::SHRD | AsmCmd::SHLD |
+            AsmCmd::ANDD | AsmCmd::ORRD | AsmCmd::XORD |
+            AsmCmd::DIVSD | AsmCmd::MODSD | AsmCmd::SARD |
+            AsmCmd::CPYD => {
+
+                if args.len() != 2 {
+                    return Err(
+                        format!("expected 2 args to {} got {:?}", cmd1, args));
+                };
+
+                // op code + dst + (src | val)
+                1 + 8 + match args[1] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => 8,
+                    Value::Int(_) | Value::Uint(_) => cmd1.width(),
+                    _ => return Err(format!("unexpected argument {:?}", args[1])),
+                }
+            },
+
+            AsmCmd::ADDF | AsmCmd::SUBF | AsmCmd::MULF | AsmCmd::DIVF |
+            AsmCmd::CMPF |
+            AsmCmd::ADDFW | AsmCmd::SUBFW | AsmCmd::MULFW | AsmCmd::DIVFW |
+            AsmCmd::CMPFW => {
+                if args.len() != 2 {
+                    return Err(
+                        format!("expected 2 args to {} got {:?}", cmd1, args));
+                };
+
+                // op code + dst + (src | val)
+                1 + 8 + match args[1] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => 8,
+                    Value::Int(_) | Value::Uint(_) | Value::Float(_) => {
+                        cmd1.width()
+                    },
+                    _ => return Err(format!("unexpected argument {:?}", args[1])),
+                }
+            },
+            AsmCmd::ITF | AsmCmd::ITFW | AsmCmd::TRNF | AsmCmd::TRNFW |
+            AsmCmd::RNDF | AsmCmd::RNDFW | AsmCmd::EXTF | AsmCmd::NRWF => {
+                if args.len() != 2 {
+                    return Err(
+                        format!("expected 2 args to {} got {:?}", cmd1, args));
+                };
+
+                1 + 8 + 8 // op code + dst + src
+            },
+            AsmCmd::EQB | AsmCmd::NEB | AsmCmd::LTB | AsmCmd::LEB | AsmCmd::GTB |
+            AsmCmd::GEB | AsmCmd::LTSB | AsmCmd::LESB | AsmCmd::GTSB | AsmCmd::GESB |
+            AsmCmd::EQW | AsmCmd::NEW | AsmCmd::LTW | AsmCmd::LEW | AsmCmd::GTW |
+            AsmCmd::GEW | AsmCmd::LTSW | AsmCmd::LESW | AsmCmd::GTSW | AsmCmd::GESW |
+            AsmCmd::EQH | AsmCmd::NEH | AsmCmd::LTH | AsmCmd::LEH | AsmCmd::GTH |
+            AsmCmd::GEH | AsmCmd::LTSH | AsmCmd::LESH | AsmCmd::GTSH | AsmCmd::GESH |
+            AsmCmd::EQD | AsmCmd::NED | AsmCmd::LTD | AsmCmd::LED | AsmCmd::GTD |
+            AsmCmd::GED | AsmCmd::LTSD | AsmCmd::LESD | AsmCmd::GTSD | AsmCmd::GESD |
+            AsmCmd::EQF | AsmCmd::NEF | AsmCmd::LTF | AsmCmd::LEF | AsmCmd::GTF |
+            AsmCmd::GEF | AsmCmd::EQFW | AsmCmd::NEFW | AsmCmd::LTFW | AsmCmd::LEFW |
+            AsmCmd::GTFW | AsmCmd::GEFW => {
+                if args.len() != 3 {
+                    return Err(
+                        format!("expected 3 args to {} got {:?}", cmd1, args));
+                };
+
+                // op code + cond + dst + a + (b | val)
+                1 + 1 + 8 + 8 + match args[2] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => 8,
+                    Value::Int(_) | Value::Uint(_) => cmd1.width(),
+                    Value::Float(_) if cmd1.base_op_code() == Op::CPF1 as u8 => {
+                        cmd1.width()
+                    },
+                    _ => return Err(format!("unexpected argument {:?}", args[2])),
+                }
+            },
+            AsmCmd::MCPY | AsmCmd::MSET | AsmCmd::MCMP => {
+                if args.len() != 3 {
+                    return Err(
+                        format!("expected 3 args to {} got {:?}", cmd1, args));
+                };
+
+                // op code + dst + (src | byte) + (len | val)
+                1 + 8 + match cmd1 {
+                    AsmCmd::MSET => 1,
+                    _ => 8,
+                } + 8
+            },
+            AsmCmd::PSHB | AsmCmd::PSHW | AsmCmd::PSHH | AsmCmd::PSHD => {
+                if args.len() != 1 {
+                    return Err(
+                        format!("expected 1 args to {} got {:?}", cmd1, args));
+                };
+
+                1 + match args[0] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => 8,
+                    Value::Int(_) | Value::Uint(_) => cmd1.width(),
+                    _ => return Err(format!("unexpected argument {:?}", args[0])),
+                }
+            },
+            AsmCmd::POPB | AsmCmd::POPW | AsmCmd::POPH | AsmCmd::POPD => {
+                // no args discards the value
+                if args.is_empty() {
+                    return Ok(1);
+                }
+
+                if args.len() != 1 {
+                    return Err(
+                        format!("expected 1 args to {} got {:?}", cmd1, args));
+                };
+
+                1 + match args[0] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => 8,
+                    _ => return Err(format!("unexpected argument {:?}", args[0])),
+                }
+            },
+
+            AsmCmd::SYS => 1 + 1, // op code + host call number
+
+            AsmCmd::SLP | AsmCmd::APG | AsmCmd::FPG | AsmCmd::FREE => {
+                if args.len() != 1 {
+                    return Err(
+                        format!("expected 1 args to {} got {:?}", cmd1, args));
+                };
+
+                1 + 1 + 8 // op code + ext code + (val | addr)
+            },
+            AsmCmd::ALLOC => {
+                if args.len() != 2 {
+                    return Err(
+                        format!("expected 2 args to {} got {:?}", cmd1, args));
+                };
+
+                1 + 1 + 8 + 8 // op code + ext code + dst + (len | addr)
+            },
+            AsmCmd::CMT => {
+                // op code + ext code + len + bytes
+                1 + 1 + 8 + match args.as_slice() {
+                    [Value::Str(x)] => x.len(),
+                    _ => return Err(
+                        format!("expected 1 string arg to {} got {:?}",
+                                cmd1, args)),
+                }
+            },
+        };
+
+        return Ok(ret)
+    }
+
+    fn compile(&self,
+               args: &Vec<Value>,
+               labels: &HashMap<String, usize>)
+        -> Result<Vec<u8>, String> {
+
+        match self {
+            AsmCmd::NOP => {
+                Ok(vec![Op::NOP as u8])
+            },
+            AsmCmd::XIT => {
+                Ok(vec![Op::XIT as u8])
+            },
+
+            /*
+             * a label or number is the address to jump to, an address
+             * is where the address to jump to is stored.
+             */
+            AsmCmd::JMP | AsmCmd::CAL => {
+                if args.len() != 1 {
+                    return Err(
+                        format!("expected 1 args to {} got {:?}", self, args));
+                };
+
+                let target = is_target(&args[0]);
+                let mut ret = match (self, target) {
+                    (AsmCmd::JMP, false) => vec![Op::JMP2 as u8],
+                    (AsmCmd::JMP, true) => vec![Op::JMP1 as u8],
+                    (_, false) => return Err(
+                        format!("{} only takes a static address", self)),
+                    (_, true) => vec![Op::CAL as u8],
+                };
+
+                let dst = if target {
+                    target_arg(&args[0], labels)?
+                } else {
+                    addr_arg(&args[0], labels)?
+                };
+
+                ret.extend_from_slice(&dst.to_le_bytes());
+                Ok(ret)
+            },
+            AsmCmd::JIT => {
+                if args.len() != 2 {
+                    return Err(
+                        format!("expected 2 args to {} got {:?}", self, args));
+                };
+
+                let dst = target_arg(&args[0], labels)?;
+                let cond = addr_arg(&args[1], labels)?;
+
+                let mut ret = vec![Op::JIT as u8];
+                ret.extend_from_slice(&dst.to_le_bytes());
+                ret.extend_from_slice(&cond.to_le_bytes());
+                Ok(ret)
+            },
+            AsmCmd::JZ | AsmCmd::JNZ | AsmCmd::JC | AsmCmd::JNC |
+            AsmCmd::JV | AsmCmd::JNV | AsmCmd::JN | AsmCmd::JNN => {
+                if args.len() != 1 {
+                    return Err(
+                        format!("expected 1 args to {} got {:?}", self, args));
+                };
+
+                let (op, mask) = match self {
+                    AsmCmd::JZ => (Op::JFS, alu::ZERO),
+                    AsmCmd::JNZ => (Op::JFC, alu::ZERO),
+                    AsmCmd::JC => (Op::JFS, alu::CARRY),
+                    AsmCmd::JNC => (Op::JFC, alu::CARRY),
+                    AsmCmd::JV => (Op::JFS, alu::OVERFLOW),
+                    AsmCmd::JNV => (Op::JFC, alu::OVERFLOW),
+                    AsmCmd::JN => (Op::JFS, alu::NEGATIVE),
+                    _ => (Op::JFC, alu::NEGATIVE),
+                };
+
+                let dst = target_arg(&args[0], labels)?;
+
+                let mut ret = vec![op as u8];
+                ret.extend_from_slice(&dst.to_le_bytes());
+                ret.push(mask);
+                Ok(ret)
+            },
+            AsmCmd::RET => {
+                Ok(vec![Op::RET as u8])
+            },
+
+            AsmCmd::PSHB | AsmCmd::PSHW | AsmCmd::PSHH | AsmCmd::PSHD => {
+                if args.len() != 1 {
+                    return Err(
+                        format!("expected 1 args to {} got {:?}", self, args));
+                };
+
+                match &args[0] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => {
+                        let mut ret =
+                            vec![self.base_op_code() + self.base_op_offset() + 1];
+                        let src = addr_arg(&args[0], labels)?;
+                        ret.extend_from_slice(&src.to_le_bytes());
+                        Ok(ret)
+                    },
+                    arg => {
+                        let mut ret =
+                            vec![self.base_op_code() + self.base_op_offset()];
+                        ret.extend_from_slice(&imm_arg(arg, self.width())?);
+                        Ok(ret)
+                    },
+                }
+            },
+            AsmCmd::POPB | AsmCmd::POPW | AsmCmd::POPH | AsmCmd::POPD => {
+                match args.len() {
+                    // discard the value
+                    0 => Ok(vec![self.base_op_code() + self.base_op_offset()]),
+                    1 => {
+                        let mut ret =
+                            vec![self.base_op_code() + self.base_op_offset() + 1];
+                        let dst = addr_arg(&args[0], labels)?;
+                        ret.extend_from_slice(&dst.to_le_bytes());
+                        Ok(ret)
+                    },
+                    _ => Err(
+                        format!("expected 1 args to {} got {:?}", self, args)),
+                }
+            },
+
+            // byte
+            AsmCmd::ADDB | AsmCmd::SUBB |
+            AsmCmd::MULB | AsmCmd::DIVB | AsmCmd::MODB |
+            AsmCmd::SHRB | AsmCmd::SHLB |
+            AsmCmd::ANDB | AsmCmd::ORRB | AsmCmd::XORB |
+            AsmCmd::DIVSB | AsmCmd::MODSB | AsmCmd::SARB |
+            AsmCmd::CPYB |
+
+            // word
+            AsmCmd::ADDW | AsmCmd::SUBW |
+            AsmCmd::MULW | AsmCmd::DIVW | AsmCmd::MODW |
+            AsmCmd::SHRW | AsmCmd::SHLW |
+            AsmCmd::ANDW | AsmCmd::ORRW | AsmCmd::XORW |
+            AsmCmd::DIVSW | AsmCmd::MODSW | AsmCmd::SARW |
+            AsmCmd::CPYW |
+
+            // half word
+            AsmCmd::ADDH | AsmCmd::SUBH |
+            AsmCmd::MULH | AsmCmd::DIVH | AsmCmd::MODH |
+            AsmCmd::SHRH | AsmCmd::SHLH |
+            AsmCmd::ANDH | AsmCmd::ORRH | AsmCmd::XORH |
+            AsmCmd::DIVSH | AsmCmd::MODSH | AsmCmd::SARH |
+            AsmCmd::CPYH |
+
+            // double word
+            AsmCmd::ADDD | AsmCmd::SUBD |
+            AsmCmd::MULD | AsmCmd::DIVD | AsmCmd::MODD |
+            AsmCmd::SHRD | AsmCmd::SHLD |
+            AsmCmd::ANDD | AsmCmd::ORRD | AsmCmd::XORD |
+            AsmCmd::DIVSD | AsmCmd::MODSD | AsmCmd::SARD |
+            AsmCmd::CPYD => {
+                if args.len() != 2 {
+                    return Err(
+                        format!("expected 2 args to {} got {:?}", self, args));
+                };
+
+                let dst = addr_arg(&args[0], labels)?;
+
+                match &args[1] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => {
+                        let mut ret =
+                            vec![self.base_op_code() + self.base_op_offset() + 1];
+                        ret.extend_from_slice(&dst.to_le_bytes());
+                        let src = addr_arg(&args[1], labels)?;
+                        ret.extend_from_slice(&src.to_le_bytes());
+                        Ok(ret)
+                    },
+                    arg => {
+                        let mut ret =
+                            vec![self.base_op_code() + self.base_op_offset()];
+                        ret.extend_from_slice(&dst.to_le_bytes());
+                        ret.extend_from_slice(&imm_arg(arg, self.width())?);
+                        Ok(ret)
+                    },
+                }
+            },
+
+            AsmCmd::ADDF | AsmCmd::SUBF | AsmCmd::MULF | AsmCmd::DIVF |
+            AsmCmd::CMPF |
+            AsmCmd::ADDFW | AsmCmd::SUBFW | AsmCmd::MULFW | AsmCmd::DIVFW |
+            AsmCmd::CMPFW => {
+                if args.len() != 2 {
+                    return Err(
+                        format!("expected 2 args to {} got {:?}", self, args));
+                };
+
+                let dst = addr_arg(&args[0], labels)?;
+
+                let mut ret = match &args[1] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => {
+                        vec![self.base_op_code() + self.base_op_offset() + 1]
+                    },
+                    _ => vec![self.base_op_code() + self.base_op_offset()],
+                };
+                ret.extend_from_slice(&dst.to_le_bytes());
+
+                match &args[1] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => {
+                        let src = addr_arg(&args[1], labels)?;
+                        ret.extend_from_slice(&src.to_le_bytes());
+                    },
+                    arg => {
+                        ret.extend_from_slice(&float_arg(arg, self.width())?);
+                    },
+                }
+
+                Ok(ret)
+            },
+            AsmCmd::ITF | AsmCmd::ITFW | AsmCmd::TRNF | AsmCmd::TRNFW |
+            AsmCmd::RNDF | AsmCmd::RNDFW | AsmCmd::EXTF | AsmCmd::NRWF => {
+                if args.len() != 2 {
+                    return Err(
+                        format!("expected 2 args to {} got {:?}", self, args));
+                };
+
+                let op = match self {
+                    AsmCmd::ITF => Op::ITF1,
+                    AsmCmd::ITFW => Op::ITF2,
+                    AsmCmd::TRNF => Op::FTI1,
+                    AsmCmd::TRNFW => Op::FTI2,
+                    AsmCmd::RNDF => Op::FRI1,
+                    AsmCmd::RNDFW => Op::FRI2,
+                    AsmCmd::EXTF => Op::FEX,
+                    _ => Op::FNR,
+                };
+
+                let dst = addr_arg(&args[0], labels)?;
+                let src = addr_arg(&args[1], labels)?;
+
+                let mut ret = vec![op as u8];
+                ret.extend_from_slice(&dst.to_le_bytes());
+                ret.extend_from_slice(&src.to_le_bytes());
+                Ok(ret)
+            },
+
+            AsmCmd::EQB | AsmCmd::NEB | AsmCmd::LTB | AsmCmd::LEB | AsmCmd::GTB |
+            AsmCmd::GEB | AsmCmd::LTSB | AsmCmd::LESB | AsmCmd::GTSB | AsmCmd::GESB |
+            AsmCmd::EQW | AsmCmd::NEW | AsmCmd::LTW | AsmCmd::LEW | AsmCmd::GTW |
+            AsmCmd::GEW | AsmCmd::LTSW | AsmCmd::LESW | AsmCmd::GTSW | AsmCmd::GESW |
+            AsmCmd::EQH | AsmCmd::NEH | AsmCmd::LTH | AsmCmd::LEH | AsmCmd::GTH |
+            AsmCmd::GEH | AsmCmd::LTSH | AsmCmd::LESH | AsmCmd::GTSH | AsmCmd::GESH |
+            AsmCmd::EQD | AsmCmd::NED | AsmCmd::LTD | AsmCmd::LED | AsmCmd::GTD |
+            AsmCmd::GED | AsmCmd::LTSD | AsmCmd::LESD | AsmCmd::GTSD | AsmCmd::GESD |
+            AsmCmd::EQF | AsmCmd::NEF | AsmCmd::LTF | AsmCmd::LEF | AsmCmd::GTF |
+            AsmCmd::GEF | AsmCmd::EQFW | AsmCmd::NEFW | AsmCmd::LTFW | AsmCmd::LEFW |
+            AsmCmd::GTFW | AsmCmd::GEFW => {
+                if args.len() != 3 {
+                    return Err(
+                        format!("expected 3 args to {} got {:?}", self, args));
+                };
+
+                let dst = addr_arg(&args[0], labels)?;
+                let a = addr_arg(&args[1], labels)?;
+                let float = self.base_op_code() == Op::CPF1 as u8;
+
+                let mut ret = match &args[2] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => {
+                        vec![self.base_op_code() + self.base_op_offset() + 1]
+                    },
+                    _ => vec![self.base_op_code() + self.base_op_offset()],
+                };
+                ret.push(self.cond() as u8);
+                ret.extend_from_slice(&dst.to_le_bytes());
+                ret.extend_from_slice(&a.to_le_bytes());
+
+                match &args[2] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => {
+                        let b = addr_arg(&args[2], labels)?;
+                        ret.extend_from_slice(&b.to_le_bytes());
+                    },
+                    arg if float => {
+                        ret.extend_from_slice(&float_arg(arg, self.width())?);
+                    },
+                    arg => {
+                        ret.extend_from_slice(&imm_arg(arg, self.width())?);
+                    },
+                }
+
+                Ok(ret)
+            },
+
+            AsmCmd::MCPY | AsmCmd::MSET | AsmCmd::MCMP => {
+                if args.len() != 3 {
+                    return Err(
+                        format!("expected 3 args to {} got {:?}", self, args));
+                };
+
+                let by_addr = match &args[2] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => true,
+                    Value::Int(_) | Value::Uint(_) => false,
+                    arg => return Err(format!("unexpected arg {:?}", arg)),
+                };
+
+                let op = match (self, by_addr) {
+                    (AsmCmd::MCPY, false) => Op::MCP1,
+                    (AsmCmd::MCPY, true) => Op::MCP2,
+                    (AsmCmd::MSET, false) => Op::MST1,
+                    (AsmCmd::MSET, true) => Op::MST2,
+                    (_, false) => Op::MCM1,
+                    (_, true) => Op::MCM2,
+                };
+
+                let mut ret = vec![op as u8];
+                let dst = addr_arg(&args[0], labels)?;
+                ret.extend_from_slice(&dst.to_le_bytes());
+
+                match self {
+                    AsmCmd::MSET => {
+                        ret.extend_from_slice(&imm_arg(&args[1], 1)?);
+                    },
+                    _ => {
+                        let src = addr_arg(&args[1], labels)?;
+                        ret.extend_from_slice(&src.to_le_bytes());
+                    },
+                }
+
+                if by_addr {
+                    let len = addr_arg(&args[2], labels)?;
+                    ret.extend_from_slice(&len.to_le_bytes());
+                } else {
+                    ret.extend_from_slice(&imm_arg(&args[2], 8)?);
+                }
+
+                Ok(ret)
+            },
+
+            AsmCmd::SYS => {
+                if args.len() != 1 {
+                    return Err(
+                        format!("expected 1 args to {} got {:?}", self, args));
+                };
+
+                let n = match args[0] {
+                    Value::Int(x) if (0..256).contains(&x) => x as u8,
+                    Value::Uint(x) if x < 256 => x as u8,
+                    _ => return Err(
+                        format!("host call must be 0 to 255 got {:?}", args[0])),
+                };
+
+                Ok(vec![Op::SYS as u8, n])
+            },
+
+            AsmCmd::SLP => {
+                if args.len() != 1 {
+                    return Err(
+                        format!("expected 1 args to {} got {:?}", self, args));
+                };
+
+                let ms = match args[0] {
+                    Value::Int(x) if x >= 0 => x as u64,
+                    Value::Uint(x) => x,
+                    _ => return Err(
+                        format!("unexpected arg {:?}", args[0])),
+                };
+
+                let mut ret = vec![Op::EXT as u8, OpExt::SLP as u8];
+                ret.extend_from_slice(&ms.to_le_bytes());
+                Ok(ret)
+            },
+            AsmCmd::APG | AsmCmd::FPG | AsmCmd::FREE => {
+                if args.len() != 1 {
+                    return Err(
+                        format!("expected 1 args to {} got {:?}", self, args));
+                };
+
+                let ext = match self {
+                    AsmCmd::APG => OpExt::APG,
+                    AsmCmd::FPG => OpExt::FPG,
+                    _ => OpExt::FRE,
+                };
+
+                let mut ret = vec![Op::EXT as u8, ext as u8];
+                let addr = addr_arg(&args[0], labels)?;
+                ret.extend_from_slice(&addr.to_le_bytes());
+                Ok(ret)
+            },
+            AsmCmd::ALLOC => {
+                if args.len() != 2 {
+                    return Err(
+                        format!("expected 2 args to {} got {:?}", self, args));
+                };
+
+                let ext = match &args[1] {
+                    Value::Label(_) | Value::Addr(_) | Value::Expr(_) => OpExt::ALC2,
+                    Value::Int(_) | Value::Uint(_) => OpExt::ALC1,
+                    arg => return Err(format!("unexpected arg {:?}", arg)),
+                };
+
+                let mut ret = vec![Op::EXT as u8, ext as u8];
+                let dst = addr_arg(&args[0], labels)?;
+                ret.extend_from_slice(&dst.to_le_bytes());
+                match ext {
+                    OpExt::ALC1 => ret.extend_from_slice(&imm_arg(&args[1], 8)?),
+                    _ => {
+                        let src = addr_arg(&args[1], labels)?;
+                        ret.extend_from_slice(&src.to_le_bytes());
+                    },
+                }
+                Ok(ret)
+            },
+            AsmCmd::CMT => {
+                let body = match args.as_slice() {
+                    [Value::Str(x)] => x,
+                    _ => return Err(
+                        format!("expected 1 string arg to {} got {:?}",
+                                self, args)),
+                };
+
+                let mut ret = vec![Op::EXT as u8, OpExt::CMT as u8];
+                ret.extend_from_slice(&body.len().to_le_bytes());
+                ret.extend_from_slice(body.as_bytes());
+                Ok(ret)
+            },
+        }
+    }
+}
+
+/**
+ * resolve an argument which refers to a location in memory
+ */
+fn addr_arg(arg: &Value, labels: &HashMap<String, usize>)
+    -> Result<usize, String> {
+
+    match arg {
+        Value::Label(x) => match labels.get(x) {
+            Some(&y) => Ok(y),
+            None => Err(format!("label {} not defined", x)),
+        },
+        Value::Addr(x) => Ok(*x),
+        Value::Expr(e) => {
+            let operand = match &**e {
+                AddrExpr::Offset(base, n) => {
+                    let base = addr_arg(base, labels)?;
+                    Operand::Direct(base.wrapping_add(*n as usize))
+                },
+                AddrExpr::Deref(base, n) => {
+                    Operand::Indirect(addr_arg(base, labels)?, *n as usize)
+                },
+                AddrExpr::Index(base, index) => {
+                    Operand::Indexed(addr_arg(base, labels)?,
+                                     addr_arg(index, labels)?)
+                },
+            };
+
+            match operand.encode() {
+                Some(x) => Ok(x),
+                None => Err(format!("{:?} does not fit in an address", arg)),
+            }
+        },
+        _ => Err(format!("arg must be addr-like got {:?}", arg)),
+    }
+}
+
+/**
+ * the little endian bytes of an immediate, checking it fits in width bytes
+ */
+fn imm_arg(arg: &Value, width: usize) -> Result<Vec<u8>, String> {
+    let bits = 8 * width as u32;
+
+    let (fits, bytes) = match *arg {
+        Value::Int(x) => {
+            let fits = bits >= 64
+                || (-(1i64 << (bits - 1)) <= x && x < 1i64 << bits);
+            (fits, x.to_le_bytes())
+        },
+        Value::Uint(x) => (bits >= 64 || x < 1u64 << bits, x.to_le_bytes()),
+        _ => return Err(format!("unexpected arg {:?}", arg)),
+    };
+
+    if !fits {
+        return Err(format!("{:?} does not fit in {} bytes", arg, width));
+    }
+
+    return Ok(bytes[..width].to_vec());
+}
+
+/**
+ * the little endian bytes of a float immediate as an f32 or f64, integers
+ * are converted
+ */
+fn float_arg(arg: &Value, width: usize) -> Result<Vec<u8>, String> {
+    let x = match *arg {
+        Value::Float(x) => x,
+        Value::Int(x) => x as f64,
+        Value::Uint(x) => x as f64,
+        _ => return Err(format!("unexpected arg {:?}", arg)),
+    };
+
+    if width == 4 {
+        return Ok((x as f32).to_le_bytes().to_vec());
+    }
+
+    return Ok(x.to_le_bytes().to_vec());
+}
+
+/**
+ * whether a jump argument is the address to jump to, as opposed to where
+ * that address is stored
+ */
+fn is_target(arg: &Value) -> bool {
+    match arg {
+        Value::Label(_) | Value::Int(_) | Value::Uint(_) => return true,
+        Value::Expr(e) => match &**e {
+            AddrExpr::Offset(base, _) => return is_target(base),
+            _ => return false,
+        },
+        _ => return false,
+    }
+}
+
+/**
+ * resolve an argument which is the address to jump to
+ */
+fn target_arg(arg: &Value, labels: &HashMap<String, usize>)
+    -> Result<usize, String> {
+
+    match arg {
+        Value::Label(_) | Value::Expr(_) => addr_arg(arg, labels),
+        Value::Int(x) if *x >= 0 => Ok(*x as usize),
+        Value::Uint(x) => Ok(*x as usize),
+        _ => Err(format!("jump target must be a label or address got {:?}", arg)),
+    }
+}
@@ -7,27 +7,46 @@ pub enum Value {
     Addr(usize),
     Int(i64),
     Uint(u64),
-    Float(f32),
+    Float(f64),
     Str(String),
+    Expr(Box<AddrExpr>),
     Err(String), // reults in a lex error
 }
 
+/**
+ * An address computed from a label or &addr
+ */
+#[derive(Debug)]
+pub enum AddrExpr {
+    Offset(Value, u64), // X+n, resolved by the assembler
+    Deref(Value, u64), // [X]+n, the address stored at X plus n
+    Index(Value, Value), // X+[Y], X plus the word stored at Y
+}
+
 impl Value {
     fn parse(s: String) -> Value {
         let first = s.as_bytes()[0] as char;
+        if first == '[' || ((first == '.' || first == '&') && s.contains('+')) {
+            return Value::parse_expr(&s);
+        }
+
         match first {
-            '0'...'9' => {
+            '0'..='9' | '-' => {
                 if let Ok(num) = s.parse::<i64>() {
                     Value::Int(num)
                 } else if let Ok(num) = s.parse::<u64>() {
                     Value::Uint(num)
-                } else if let Ok(num) = s.parse::<f32>() {
+                } else if let Ok(num) = s.parse::<f64>() {
                     Value::Float(num)
                 } else {
                     Value::Err(format!("unexpected string {}", s))
                 }
             },
             '"' => {
+                if s.len() < 2 || !s.ends_with('"') {
+                    return Value::Err(format!("unterminated string {}", s));
+                }
+
                 // remove the quotes
                 Value::Str(s.get(1..s.len()-1).unwrap().to_string())
             },
@@ -35,13 +54,81 @@ impl Value {
             '&' => {
                 match s.get(1..).unwrap().to_string().parse::<usize>() {
                     Ok(x) => Value::Addr(x),
-                    Err(x) => Value::Err(format!("bad address {}", s))
+                    Err(_) => Value::Err(format!("bad address {}", s))
                 }
             },
             // maybe someday this will be used for registers
             _ => Value::Err(format!("unexpected string {}", s)),
         }
     }
+
+    /**
+     * [X], [X]+n, X+n or X+[Y], where X and Y are static addresses
+     */
+    fn parse_expr(s: &str) -> Value {
+        if let Some(rest) = s.strip_prefix('[') {
+            let end = match rest.find(']') {
+                Some(end) => end,
+                None => return Value::Err(format!("unterminated [ in {}", s)),
+            };
+
+            let base = match Value::parse_static(&rest[..end]) {
+                Value::Err(x) => return Value::Err(x),
+                base => base,
+            };
+
+            let offset = match &rest[end+1..] {
+                "" => 0,
+                after => match after.strip_prefix('+').map(str::parse::<u64>) {
+                    Some(Ok(n)) => n,
+                    _ => return Value::Err(format!("bad offset in {}", s)),
+                },
+            };
+
+            return Value::Expr(Box::new(AddrExpr::Deref(base, offset)));
+        }
+
+        let (base, rest) = match s.find('+') {
+            Some(i) => (&s[..i], &s[i+1..]),
+            None => return Value::Err(format!("unexpected string {}", s)),
+        };
+
+        let base = match Value::parse_static(base) {
+            Value::Err(x) => return Value::Err(x),
+            base => base,
+        };
+
+        if let Some(index) = rest.strip_prefix('[').and_then(|r| r.strip_suffix(']')) {
+            return match Value::parse_static(index) {
+                Value::Err(x) => Value::Err(x),
+                index => Value::Expr(Box::new(AddrExpr::Index(base, index))),
+            };
+        }
+
+        match rest.parse::<u64>() {
+            Ok(n) => return Value::Expr(Box::new(AddrExpr::Offset(base, n))),
+            Err(_) => return Value::Err(format!("bad offset in {}", s)),
+        }
+    }
+
+    /**
+     * a label, &addr or either plus a number
+     */
+    fn parse_static(s: &str) -> Value {
+        if s.is_empty() {
+            return Value::Err("expected an address".to_string());
+        }
+
+        match Value::parse(s.to_string()) {
+            v @ Value::Label(_) | v @ Value::Addr(_) => return v,
+            Value::Expr(e) => match *e {
+                AddrExpr::Offset(..) => return Value::Expr(e),
+                _ => return Value::Err(format!("{} is not a static address", s)),
+            },
+            Value::Err(x) => return Value::Err(x),
+            _ => return Value::Err(format!("{} is not an address", s)),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -84,6 +171,17 @@ impl AstNode {
                         consumeln_ws(chars);
                     },
                     '\n' | ';' => break, // command line ends at \n or comment
+                    '"' if arg.is_empty() => {
+                        // strings may have whitespace and ; in them
+                        arg.push(c);
+                        chars.next();
+                        for c in chars.by_ref() {
+                            arg.push(c);
+                            if c == '"' {
+                                break
+                            }
+                        }
+                    },
                     _ => {
                         arg.push(c);
                         chars.next();
@@ -110,7 +208,7 @@ impl AstNode {
 
             let mut label = String::new();
 
-            while let Some(c) = chars.next() {
+            for c in chars.by_ref() {
                 match c {
                     ' ' | '\t' => {
                         consume_ws(chars);
@@ -132,7 +230,7 @@ impl AstNode {
 
             let mut body = String::new();
 
-            while let Some(c) = chars.next() {
+            for c in chars.by_ref() {
                 match c {
                     '\n' => break,
                     _ => body.push(c),
@@ -170,9 +268,9 @@ pub fn parse(src: String) -> Result<AstNode, String> {
 
     let mut chars = src.chars().peekable();
     while let Some(&c) = chars.peek() {
-        let mut res: Result<AstNode, String>;
+        let res: Result<AstNode, String>;
         match c {
-            'a'...'z' | 'A'...'Z' => {
+            'a'..='z' | 'A'..='Z' => {
                 res = AstNode::parse_cmd(&mut chars);
             },
             '.' => {
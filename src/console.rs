@@ -0,0 +1,141 @@
+use std::cell::RefCell;
+use std::io;
+use std::io::{BufRead, Read, Write};
+use std::rc::Rc;
+
+use crate::host::Host;
+use crate::memory::{Memory, Perm};
+use crate::trap::{Fault, TrapKind};
+
+/*
+ * built in host calls for console i/o, arguments are words pushed in the
+ * order listed
+ *
+ * PUTB byte            write a byte, returns 1
+ * WRITE addr len       write len bytes at addr, returns len
+ * GETB addr            read a byte into addr, returns 1 or 0 at EOF
+ * GETLN addr len       read a line, up to len bytes including the
+ *                      newline, into addr. returns how many bytes were
+ *                      read, 0 at EOF
+ */
+pub const PUTB: u8 = 0;
+pub const WRITE: u8 = 1;
+pub const GETB: u8 = 2;
+pub const GETLN: u8 = 3;
+
+/**
+ * The streams the console host calls use, stdin and stdout by default
+ */
+pub struct Console {
+    pub input: Box<dyn BufRead>,
+    pub output: Box<dyn Write>,
+}
+
+impl Console {
+    pub fn stdio() -> Console {
+        return Console{
+            input: Box::new(io::BufReader::new(io::stdin())),
+            output: Box::new(io::stdout()),
+        };
+    }
+}
+
+fn io_error(_: io::Error) -> Fault {
+    return TrapKind::HostError.into();
+}
+
+/**
+ * fault unless the len bytes at addr can be written, so input isn't
+ * consumed for a read that can't be stored
+ */
+fn check_dst(memory: &Memory, addr: usize, len: usize) -> Result<(), Fault> {
+    memory.check_range(addr, len)?;
+    return memory.check(addr, len, Perm::WRITE);
+}
+
+/**
+ * register the console host calls, sharing the console with the caller
+ * so it can swap the streams later
+ */
+pub fn register(host: &mut Host, console: &Rc<RefCell<Console>>) {
+    let c = console.clone();
+    host.register(PUTB, "putb", Box::new(move |memory: &mut Memory| {
+        let byte = memory.pop::<u64>()? as u8;
+
+        let out = &mut c.borrow_mut().output;
+        out.write_all(&[byte]).map_err(io_error)?;
+        out.flush().map_err(io_error)?;
+        return Ok(1);
+    }));
+
+    let c = console.clone();
+    host.register(WRITE, "write", Box::new(move |memory: &mut Memory| {
+        let len = memory.pop::<u64>()? as usize;
+        let addr = memory.pop::<u64>()? as usize;
+
+        let out = &mut c.borrow_mut().output;
+        out.write_all(&memory.read_range(addr, len)?).map_err(io_error)?;
+        out.flush().map_err(io_error)?;
+        return Ok(len as u64);
+    }));
+
+    let c = console.clone();
+    host.register(GETB, "getb", Box::new(move |memory: &mut Memory| {
+        let addr = memory.pop::<u64>()? as usize;
+        check_dst(memory, addr, 1)?;
+
+        let mut byte = [0u8];
+        let n = c.borrow_mut().input.read(&mut byte).map_err(io_error)?;
+        if n == 0 {
+            return Ok(0);
+        }
+
+        memory.set(addr, byte[0])?;
+        return Ok(1);
+    }));
+
+    let c = console.clone();
+    host.register(GETLN, "getln", Box::new(move |memory: &mut Memory| {
+        let len = memory.pop::<u64>()? as usize;
+        let addr = memory.pop::<u64>()? as usize;
+        check_dst(memory, addr, len)?;
+
+        // take at most len bytes, so the rest of a long line is left for
+        // the next read
+        let mut line = vec![];
+        let input = &mut c.borrow_mut().input;
+        input.take(len as u64)
+            .read_until(b'\n', &mut line)
+            .map_err(io_error)?;
+
+        memory.write_range(addr, &line)?;
+        return Ok(line.len() as u64);
+    }));
+}
+
+/**
+ * A Write which keeps what is written so it can be read back after
+ * handing a clone to the vm, for capturing a program's output.
+ */
+#[derive(Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn new() -> Capture {
+        return Capture::default();
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        return self.0.borrow().clone();
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return self.0.borrow_mut().write(buf);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::io::{BufRead, Write};
+
+use crate::alu;
+use crate::disasm::disassemble;
+use crate::memory::{CODE_OFFSET, FLAGS_ADDR};
+use crate::vm::{State, Vm};
+
+const MAX_DUMP: usize = 64 * 1024; // most bytes x will dump
+
+const HELP: &str = "\
+commands:
+    b, break [addr|label]   set a breakpoint, or list them with no args
+    d, delete <addr|label>  delete a breakpoint
+    s, step [n]             execute n instructions, default 1
+    c, continue             run until a breakpoint, exit or trap
+    r, regs                 show pc, sp, flags and the vm state
+    x <addr|label> [len]    hex dump len bytes, default 64, at most 65536
+    l, list [n]             disassemble n instructions around pc, default 5
+    save <file>             write a snapshot of the vm to file
+    load <file>             restore the vm from a snapshot
+    h, help                 show this message
+    q, quit                 exit the debugger
+
+addresses are numbers, 0x prefixed hex, &addr like the assembler, or labels
+from a symbol file.";
+
+/**
+ * A line oriented debugger for programs running on a Vm
+ */
+pub struct Debugger {
+    vm: Vm,
+    labels: HashMap<String, usize>,
+    breakpoints: BTreeSet<usize>,
+}
+
+impl Debugger {
+    pub fn new(vm: Vm, labels: HashMap<String, usize>) -> Debugger {
+        return Debugger{
+            vm,
+            labels,
+            breakpoints: BTreeSet::new(),
+        };
+    }
+
+    pub fn vm(&self) -> &Vm {
+        return &self.vm;
+    }
+
+    /**
+     * Read commands from input until it runs out or the user quits,
+     * writing the prompt and results to out.
+     */
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut out: W)
+        -> io::Result<()> {
+
+        let mut lines = input.lines();
+        loop {
+            write!(out, "(mdb) ")?;
+            out.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+
+            if !self.command(&line, &mut out)? {
+                break;
+            }
+        }
+
+        return Ok(());
+    }
+
+    /**
+     * Execute a single command, returns false when the user quits.
+     */
+    pub fn command<W: Write>(&mut self, line: &str, out: &mut W)
+        -> io::Result<bool> {
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (cmd, args) = match words.split_first() {
+            Some((cmd, args)) => (*cmd, args),
+            None => return Ok(true),
+        };
+
+        match (cmd, args) {
+            ("b", []) | ("break", []) => {
+                for addr in &self.breakpoints {
+                    writeln!(out, "{}{}", addr, self.label_suffix(*addr))?;
+                }
+            },
+            ("b", [loc]) | ("break", [loc]) => match self.addr(loc) {
+                Ok(addr) => {
+                    self.breakpoints.insert(addr);
+                    writeln!(out, "breakpoint at {}{}",
+                             addr, self.label_suffix(addr))?;
+                },
+                Err(msg) => writeln!(out, "{}", msg)?,
+            },
+            ("d", [loc]) | ("delete", [loc]) => match self.addr(loc) {
+                Ok(addr) => {
+                    if !self.breakpoints.remove(&addr) {
+                        writeln!(out, "no breakpoint at {}", addr)?;
+                    }
+                },
+                Err(msg) => writeln!(out, "{}", msg)?,
+            },
+            ("s", _) | ("step", _) if args.len() <= 1 => {
+                let n = match args.first().map(|n| n.parse::<usize>()) {
+                    None => 1,
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => {
+                        writeln!(out, "bad step count {}", args[0])?;
+                        return Ok(true);
+                    },
+                };
+
+                self.vm.run_until(n);
+                self.show_stop(out)?;
+            },
+            ("c", []) | ("continue", []) => {
+                // always take a step so we can continue from a breakpoint
+                while self.vm.step() == State::Running
+                    && !self.breakpoints.contains(&self.vm.pc()) {}
+
+                self.show_stop(out)?;
+            },
+            ("r", []) | ("regs", []) => {
+                writeln!(out, "pc    {}{}", self.vm.pc(),
+                         self.label_suffix(self.vm.pc()))?;
+                writeln!(out, "sp    {}", self.vm.sp())?;
+                let flags: u8 = self.vm.read(FLAGS_ADDR).unwrap_or(0);
+                let names: String = [
+                    (alu::ZERO, 'Z'), (alu::CARRY, 'C'),
+                    (alu::OVERFLOW, 'V'), (alu::NEGATIVE, 'N'),
+                ].iter()
+                    .map(|&(bit, c)| if flags & bit != 0 { c } else { '-' })
+                    .collect();
+                writeln!(out, "flags {}", names)?;
+                writeln!(out, "state {:?}", self.vm.state())?;
+            },
+            ("x", _) if !args.is_empty() && args.len() <= 2 => {
+                let addr = match self.addr(args[0]) {
+                    Ok(addr) => addr,
+                    Err(msg) => {
+                        writeln!(out, "{}", msg)?;
+                        return Ok(true);
+                    },
+                };
+
+                let len = match args.get(1).map(|n| n.parse::<usize>()) {
+                    None => 64,
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => {
+                        writeln!(out, "bad length {}", args[1])?;
+                        return Ok(true);
+                    },
+                };
+
+                if len > MAX_DUMP {
+                    writeln!(out, "can't dump more than {} bytes at once", MAX_DUMP)?;
+                    return Ok(true);
+                }
+
+                if addr.checked_add(len).is_none() {
+                    writeln!(out, "{} bytes from {} runs past the end of memory",
+                             len, addr)?;
+                    return Ok(true);
+                }
+
+                self.dump(addr, len, out)?;
+            },
+            ("l", _) | ("list", _) if args.len() <= 1 => {
+                let n = match args.first().map(|n| n.parse::<usize>()) {
+                    None => 5,
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => {
+                        writeln!(out, "bad count {}", args[0])?;
+                        return Ok(true);
+                    },
+                };
+
+                self.list(n, out)?;
+            },
+            ("save", [file]) => {
+                let res = fs::File::create(file)
+                    .and_then(|f| self.vm.snapshot(io::BufWriter::new(f)));
+                match res {
+                    Ok(()) => writeln!(out, "saved {}", file)?,
+                    Err(err) => writeln!(out, "could not save {}: {}", file, err)?,
+                }
+            },
+            ("load", [file]) => {
+                let res = fs::File::open(file)
+                    .and_then(|f| self.vm.restore(io::BufReader::new(f)));
+                match res {
+                    Ok(()) => self.show_stop(out)?,
+                    Err(err) => writeln!(out, "could not load {}: {}", file, err)?,
+                }
+            },
+            ("h", _) | ("help", _) => writeln!(out, "{}", HELP)?,
+            ("q", _) | ("quit", _) => return Ok(false),
+            _ => writeln!(out, "unknown command {:?}, try help", line.trim())?,
+        }
+
+        return Ok(true);
+    }
+
+    /**
+     * parse an address argument
+     */
+    fn addr(&self, s: &str) -> Result<usize, String> {
+        if s.starts_with('.') {
+            return self.labels.get(s)
+                .copied()
+                .ok_or(format!("unknown label {}", s));
+        }
+
+        let res = if let Some(hex) = s.strip_prefix("0x") {
+            usize::from_str_radix(hex, 16)
+        } else if let Some(dec) = s.strip_prefix('&') {
+            dec.parse()
+        } else {
+            s.parse()
+        };
+
+        return res.map_err(|_| format!("bad address {}", s));
+    }
+
+    fn labels_at(&self, addr: usize) -> Vec<&String> {
+        let mut names: Vec<&String> = self.labels.iter()
+            .filter(|&(_, &a)| a == addr)
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        return names;
+    }
+
+    fn label_suffix(&self, addr: usize) -> String {
+        match self.labels_at(addr).first() {
+            Some(name) => format!(" <{}>", name),
+            None => String::new(),
+        }
+    }
+
+    fn show_stop<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        match self.vm.state() {
+            State::Running => {
+                if self.breakpoints.contains(&self.vm.pc()) {
+                    write!(out, "breakpoint, ")?;
+                }
+            },
+            State::Exited(code) => {
+                return writeln!(out, "exited with {}", code);
+            },
+            State::Trapped(trap) => {
+                write!(out, "trap: {}, ", trap)?;
+            },
+        }
+
+        let pc = self.vm.pc();
+        match disassemble(self.vm.memory(), pc) {
+            Ok((text, _)) => {
+                writeln!(out, "pc {}{}: {}", pc, self.label_suffix(pc), text)
+            },
+            Err(_) => writeln!(out, "pc {}{}: ??", pc, self.label_suffix(pc)),
+        }
+    }
+
+    fn dump<W: Write>(&self, addr: usize, len: usize, out: &mut W)
+        -> io::Result<()> {
+
+        let memory = self.vm.memory();
+
+        for line in (addr..addr+len).step_by(16) {
+            let end = usize::min(line.saturating_add(16), addr + len);
+
+            write!(out, "{:>8}:", line)?;
+            let mut ascii = String::new();
+            for a in line..end {
+                // bytes are read one at a time so unmapped memory can be
+                // shown next to mapped memory
+                match memory.bytes(a, 1) {
+                    Ok(b) => {
+                        write!(out, " {:02x}", b[0])?;
+                        ascii.push(match b[0] {
+                            0x20..=0x7e => b[0] as char,
+                            _ => '.',
+                        });
+                    },
+                    Err(_) => {
+                        write!(out, " ??")?;
+                        ascii.push(' ');
+                    },
+                }
+            }
+
+            writeln!(out, "{:>width$}|{}|", "", ascii,
+                     width = 3 * (16 - (end - line)) + 2)?;
+        }
+
+        return Ok(());
+    }
+
+    /**
+     * Disassemble around pc. Instructions can't be decoded backwards, so
+     * the program is decoded from the start to find the ones before pc.
+     */
+    fn list<W: Write>(&self, n: usize, out: &mut W) -> io::Result<()> {
+        let memory = self.vm.memory();
+        let pc = self.vm.pc();
+
+        let mut starts = vec![];
+        let mut addr = CODE_OFFSET;
+        while addr < pc {
+            starts.push(addr);
+            match disassemble(memory, addr) {
+                Ok((_, size)) => addr += size,
+                Err(_) => break,
+            }
+        }
+
+        // if decoding from the start doesn't land on pc, pc is in data
+        // or the middle of an instruction, so start listing from pc
+        let before = if addr == pc {
+            &starts[starts.len().saturating_sub(n / 2)..]
+        } else {
+            &starts[..0]
+        };
+
+        let mut addr = before.first().copied().unwrap_or(pc);
+        for _ in 0..n {
+            for name in self.labels_at(addr) {
+                writeln!(out, "{}:", name)?;
+            }
+
+            let marker = if addr == pc { "=>" } else { "  " };
+            match disassemble(memory, addr) {
+                Ok((text, size)) => {
+                    writeln!(out, "{} {:>8}  {}", marker, addr, text)?;
+                    addr += size;
+                },
+                Err(_) => {
+                    writeln!(out, "{} {:>8}  ??", marker, addr)?;
+                    break;
+                },
+            }
+        }
+
+        return Ok(());
+    }
+}
@@ -1,16 +1,30 @@
+/*
+ * An enum whose variants are numbered densely from 0, like op codes, with
+ * conversions to and from bytes and strings. Decoding a byte is checked,
+ * so arbitrary bytes from memory can be decoded safely.
+ */
 macro_rules! dense_enum {
     ($name:ident;
         $($var:ident) , * ,
     ) => {
-        #[allow(dead_code)]
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
         pub enum $name {
             $($var) , *
         }
 
         impl $name {
-            pub fn from_int(i:u8) -> $name {
-                return unsafe { std::mem::transmute::<u8, $name>(i) }
+            const ALL: &'static [$name] = &[$($name::$var) , *];
+
+            /**
+             * number of variants
+             */
+            pub const COUNT: usize = $name::ALL.len();
+
+            /**
+             * all the variants in order
+             */
+            pub fn iter() -> impl Iterator<Item = $name> {
+                return $name::ALL.iter().copied();
             }
 
             pub fn to_str(&self) -> &'static str {
@@ -18,13 +32,34 @@ macro_rules! dense_enum {
                     $($name::$var => stringify!($var)) , *
                 }
             }
+        }
+
+        impl std::convert::TryFrom<u8> for $name {
+            type Error = String;
+
+            fn try_from(i: u8) -> Result<$name, String> {
+                match $name::ALL.get(i as usize) {
+                    Some(&x) => Ok(x),
+                    None => Err(format!("{} not a[n] {}", i, stringify!($name))),
+                }
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = String;
 
-            pub fn from_string(s: String) -> Result<$name, String> {
+            fn from_str(s: &str) -> Result<$name, String> {
                 match s.to_uppercase().as_str() {
                     $(stringify!($var) => Ok($name::$var)) , * ,
                     _ => Err(format!("{} not a[n] {}", s, stringify!($name))),
                 }
             }
         }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "{}", self.to_str())
+            }
+        }
     }
 }
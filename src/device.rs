@@ -0,0 +1,19 @@
+use crate::trap::Fault;
+
+/**
+ * A host device mapped into guest memory with Memory::map_device. Guest
+ * loads and stores to the mapped range call read and write with the
+ * offset into the range, instead of touching memory. Returning a Fault
+ * traps the program.
+ */
+pub trait Device {
+    /**
+     * fill buf with the bytes at offset
+     */
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Fault>;
+
+    /**
+     * the guest stored bytes at offset
+     */
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Fault>;
+}
@@ -0,0 +1,95 @@
+use std::convert::TryFrom;
+
+use crate::memory::Memory;
+use crate::op_code::{Arg, Cond, Op, OpExt, Operand};
+use crate::trap::{Fault, TrapKind};
+
+/**
+ * Disassemble the instruction at addr, returning its text and how many
+ * bytes it takes up. Addresses are written like the assembler's &addr,
+ * [&addr]+n and &addr+[&addr], values as plain numbers.
+ */
+pub fn disassemble(memory: &Memory, addr: usize) -> Result<(String, usize), Fault> {
+    let op: u8 = memory.get(addr)?;
+    let code = match Op::try_from(op) {
+        Ok(code) => code,
+        Err(_) => return Err(Fault::new(TrapKind::IllegalInstruction, addr)),
+    };
+
+    let mut text = code.to_string();
+    let mut pc = addr + 1;
+
+    pc = disassemble_args(memory, code.args(), pc, &mut text)?;
+
+    if code == Op::EXT {
+        let ext: u8 = memory.get(pc)?;
+        let ext = match OpExt::try_from(ext) {
+            Ok(ext) => ext,
+            Err(_) => return Err(Fault::new(TrapKind::IllegalInstruction, pc)),
+        };
+        pc += 1;
+
+        text.push(' ');
+        text.push_str(ext.to_str());
+
+        let start = pc;
+        pc = disassemble_args(memory, ext.args(), pc, &mut text)?;
+
+        // skip the comment body
+        if ext == OpExt::CMT {
+            let len: usize = memory.get(start)?;
+            pc = match pc.checked_add(len) {
+                Some(pc) => pc,
+                None => return Err(Fault::new(TrapKind::OutOfBounds, start)),
+            };
+        }
+    }
+
+    return Ok((text, pc - addr));
+}
+
+fn disassemble_args(memory: &Memory, args: &[Arg], mut pc: usize,
+                    text: &mut String) -> Result<usize, Fault> {
+
+    for arg in args {
+        match arg {
+            Arg::Addr => {
+                let raw: usize = memory.get(pc)?;
+                match Operand::decode(raw) {
+                    Some(operand) => text.push_str(&format!(" {}", operand)),
+                    None => text.push_str(&format!(" &{}", raw)),
+                }
+                pc += 8;
+            },
+            Arg::Val(n) => {
+                // values are little endian
+                let val = memory.bytes(pc, *n)?
+                    .iter()
+                    .rev()
+                    .fold(0u64, |acc, &b| (acc << 8) | b as u64);
+                text.push_str(&format!(" {}", val));
+                pc += n;
+            },
+            Arg::Cond => {
+                let cond: u8 = memory.get(pc)?;
+                match Cond::try_from(cond) {
+                    Ok(cond) => text.push_str(&format!(" {}", cond)),
+                    Err(_) => text.push_str(&format!(" {}", cond)),
+                }
+                pc += 1;
+            },
+            Arg::Float(4) => {
+                let val: f32 = memory.get(pc)?;
+                text.push_str(&format!(" {:?}", val));
+                pc += 4;
+            },
+            Arg::Float(n) => {
+                let val: f64 = memory.get(pc)?;
+                text.push_str(&format!(" {:?}", val));
+                pc += n;
+            },
+        }
+    }
+
+    return Ok(pc);
+}
@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use crate::memory::Memory;
+use crate::snapshot::{invalid, Decoder, Encoder};
+use crate::trap::{Fault, TrapKind};
+
+const MIN_BLOCK: usize = 16; // size of the smallest class
+
+/**
+ * a block handed out by the heap
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    Small(usize), // size class, sharing a page with others of the class
+    Large(usize), // number of whole pages
+}
+
+/**
+ * Allocator behind the guest's ALC and FRE. Small blocks are rounded up
+ * to a power of two size class, and each class carves pages into blocks
+ * kept on a free list. Anything over half a page gets pages of its own,
+ * which go back to memory when it is freed.
+ */
+#[derive(Default)]
+pub struct Heap {
+    free: Vec<Vec<usize>>, // free blocks of each size class
+    pages: BTreeSet<usize>, // pages carved into small blocks
+    blocks: BTreeMap<usize, (Block, bool)>, // address -> block, in use
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        return Heap::default();
+    }
+
+    /**
+     * the smallest class holding size bytes, None if it needs whole pages
+     */
+    fn class_of(size: usize, page_size: usize) -> Option<usize> {
+        if size > page_size / 2 {
+            return None;
+        }
+
+        let mut class = 0;
+        while MIN_BLOCK << class < size {
+            class += 1;
+        }
+
+        if MIN_BLOCK << class > page_size / 2 {
+            return None;
+        }
+
+        return Some(class);
+    }
+
+    /**
+     * allocate size zeroed bytes, returning their address
+     */
+    pub fn alloc(&mut self, memory: &mut Memory, size: usize) -> Result<usize, Fault> {
+        let page_size = memory.config().page_size;
+
+        match Heap::class_of(size, page_size) {
+            Some(class) => {
+                let addr = self.take(memory, class)?;
+                memory.fill(addr, 0, MIN_BLOCK << class)?;
+                self.blocks.insert(addr, (Block::Small(class), true));
+                return Ok(addr);
+            },
+            None => {
+                let n = size.max(1).div_ceil(page_size);
+                let addr = memory.alloc_pages(n)?;
+                self.blocks.insert(addr, (Block::Large(n), true));
+                return Ok(addr);
+            },
+        }
+    }
+
+    /**
+     * a free block of class, carving a new page into blocks if there are
+     * none left
+     */
+    fn take(&mut self, memory: &mut Memory, class: usize) -> Result<usize, Fault> {
+        if self.free.len() <= class {
+            self.free.resize_with(class + 1, Vec::new);
+        }
+
+        if self.free[class].is_empty() {
+            let page = memory.alloc_page()?;
+            let size = MIN_BLOCK << class;
+            self.pages.insert(page);
+
+            // reversed so blocks are handed out in address order
+            for i in (0..memory.config().page_size / size).rev() {
+                let addr = page + i * size;
+                self.free[class].push(addr);
+                self.blocks.insert(addr, (Block::Small(class), false));
+            }
+        }
+
+        return Ok(self.free[class].pop().unwrap());
+    }
+
+    /**
+     * free the block at addr, which must have come from alloc and not
+     * been freed since
+     */
+    pub fn free(&mut self, memory: &mut Memory, addr: usize) -> Result<(), Fault> {
+        let (block, used) = match self.blocks.get_mut(&addr) {
+            Some(entry) => entry,
+            None => return Err(Fault::new(TrapKind::InvalidFree, addr)),
+        };
+
+        if !*used {
+            return Err(Fault::new(TrapKind::DoubleFree, addr));
+        }
+
+        match *block {
+            Block::Small(class) => self.free[class].push(addr),
+            Block::Large(n) => {
+                // check them all first so a missing page frees nothing
+                let page_size = memory.config().page_size;
+                memory.check_range(addr, n * page_size)?;
+                for i in 0..n {
+                    memory.free_page(addr + i * page_size)?;
+                }
+            },
+        }
+
+        *used = false;
+
+        return Ok(());
+    }
+
+    /**
+     * whether addr is in a page holding heap blocks, which only FRE may
+     * give back
+     */
+    pub fn owns(&self, addr: usize, page_size: usize) -> bool {
+        if let Some(&page) = self.pages.range(..=addr).next_back() {
+            if addr - page < page_size {
+                return true;
+            }
+        }
+
+        match self.blocks.range(..=addr).next_back() {
+            Some((&start, &(Block::Large(n), true))) => return addr - start < n * page_size,
+            _ => return false,
+        }
+    }
+
+    pub(crate) fn save(&self, enc: &mut Encoder) -> io::Result<()> {
+        enc.usize(self.free.len())?;
+        for list in &self.free {
+            enc.usize(list.len())?;
+            for &addr in list {
+                enc.usize(addr)?;
+            }
+        }
+
+        enc.usize(self.pages.len())?;
+        for &page in &self.pages {
+            enc.usize(page)?;
+        }
+
+        enc.usize(self.blocks.len())?;
+        for (&addr, &(block, used)) in &self.blocks {
+            enc.usize(addr)?;
+            match block {
+                Block::Small(class) => {
+                    enc.u8(0)?;
+                    enc.usize(class)?;
+                },
+                Block::Large(n) => {
+                    enc.u8(1)?;
+                    enc.usize(n)?;
+                },
+            }
+            enc.u8(used as u8)?;
+        }
+
+        return Ok(());
+    }
+
+    pub(crate) fn restore(&mut self, dec: &mut Decoder) -> io::Result<()> {
+        let mut free = vec![];
+        for _ in 0..dec.usize()? {
+            let mut list = vec![];
+            for _ in 0..dec.usize()? {
+                list.push(dec.usize()?);
+            }
+            free.push(list);
+        }
+
+        let mut pages = BTreeSet::new();
+        for _ in 0..dec.usize()? {
+            pages.insert(dec.usize()?);
+        }
+
+        let mut blocks = BTreeMap::new();
+        for _ in 0..dec.usize()? {
+            let addr = dec.usize()?;
+            let block = match dec.u8()? {
+                0 => Block::Small(dec.usize()?),
+                1 => Block::Large(dec.usize()?),
+                b => return Err(invalid(format!("bad heap block kind {}", b))),
+            };
+            if let Block::Small(class) = block {
+                if class >= free.len() {
+                    return Err(invalid(format!("bad size class {}", class)));
+                }
+            }
+            blocks.insert(addr, (block, dec.bool()?));
+        }
+
+        self.free = free;
+        self.pages = pages;
+        self.blocks = blocks;
+
+        return Ok(());
+    }
+}
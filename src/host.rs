@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+
+use crate::memory::Memory;
+use crate::trap::Fault;
+
+/**
+ * A host function guest code can call with SYS n. Arguments are pushed
+ * onto the stack by the guest, so the host function pops them off in
+ * reverse order. The returned value is pushed onto the stack for the
+ * guest to pop, and returning a Fault traps the program.
+ */
+pub type HostFn = Box<dyn FnMut(&mut Memory) -> Result<u64, Fault>>;
+
+pub struct HostCall {
+    pub name: String,
+    pub f: HostFn,
+}
+
+/**
+ * Registry of host functions by number
+ */
+#[derive(Default)]
+pub struct Host {
+    calls: BTreeMap<u8, HostCall>,
+}
+
+impl Host {
+    pub fn new() -> Host {
+        return Host{ calls: BTreeMap::new() };
+    }
+
+    /**
+     * register a host function, replacing any previously registered with
+     * the same number
+     */
+    pub fn register(&mut self, n: u8, name: &str, f: HostFn) {
+        self.calls.insert(n, HostCall{ name: name.to_string(), f });
+    }
+
+    pub fn unregister(&mut self, n: u8) -> Option<HostCall> {
+        return self.calls.remove(&n);
+    }
+
+    pub fn get_mut(&mut self, n: u8) -> Option<&mut HostCall> {
+        return self.calls.get_mut(&n);
+    }
+
+    /**
+     * number of the host function with the given name
+     */
+    pub fn number(&self, name: &str) -> Option<u8> {
+        return self.calls.iter()
+            .find(|(_, call)| call.name == name)
+            .map(|(&n, _)| n);
+    }
+
+    /**
+     * the numbers and names of registered host functions
+     */
+    pub fn calls(&self) -> impl Iterator<Item = (u8, &str)> {
+        return self.calls.iter().map(|(&n, call)| (n, call.name.as_str()));
+    }
+}
@@ -0,0 +1,37 @@
+/*
+ * my vm, a (relatively) simple virtual machine and assembler.
+ *
+ * The vm and assembler are exposed here so they can be embedded in other
+ * programs, the mvm and mas binaries are thin front ends over this crate.
+ */
+
+// op codes and assembler commands are named like the mnemonics, and
+// explicit returns are used throughout
+#![allow(clippy::upper_case_acronyms, clippy::needless_return)]
+
+#[macro_use]
+mod dense_enum;
+
+pub mod op_code;
+pub mod memory;
+pub mod ast;
+pub mod alu;
+pub mod asm;
+pub mod console;
+pub mod debugger;
+pub mod device;
+pub mod disasm;
+pub mod heap;
+pub mod host;
+pub mod snapshot;
+pub mod trace;
+pub mod trap;
+pub mod vm;
+
+pub use asm::assemble;
+pub use device::Device;
+pub use host::HostFn;
+pub use memory::{Memory, MemoryConfig};
+pub use trace::Tracer;
+pub use trap::{Fault, Trap, TrapKind};
+pub use vm::{ExitStatus, State, Vm};
@@ -1,143 +1,861 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io;
+use std::mem::size_of;
+use std::ops::BitOr;
+
+use crate::device::Device;
+use crate::op_code::Operand;
+use crate::snapshot::{invalid, Decoder, Encoder};
+use crate::trap::{Fault, TrapKind};
+
 const KB:usize = 1024;
-const MB:usize = KB * KB;
 
-const FAST_SIZE: usize = 32 * KB;
-const PAGE_SIZE: usize = 4 * KB;
-const MAX_FRAME: usize = 64;
+pub const MAX_FRAME: usize = 64; // fast memory starts here
 
 pub const PROG_OFFSET:usize = MAX_FRAME;
+pub const FLAGS_ADDR:usize = PROG_OFFSET + 1; // set by integer ops
+pub const CODE_OFFSET:usize = PROG_OFFSET + 2;
+
+/**
+ * Sizes of the parts of memory, fixed when it is created.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryConfig {
+    pub fast_size: usize, // holds the program and the stack
+    pub page_size: usize,
+    pub frame_size: usize, // relative addresses, at most MAX_FRAME
+    pub max_pages: usize, // pages allocated at once
+}
+
+impl Default for MemoryConfig {
+    fn default() -> MemoryConfig {
+        return MemoryConfig{
+            fast_size: 32 * KB,
+            page_size: 4 * KB,
+            frame_size: MAX_FRAME,
+            max_pages: 256 * KB, // 1 GB of 4 KB pages
+        };
+    }
+}
+
+impl MemoryConfig {
+    /**
+     * check the config can hold a program of code_len bytes
+     */
+    pub fn validate(&self, code_len: usize) -> Result<(), String> {
+        if self.frame_size == 0 || self.frame_size > MAX_FRAME {
+            return Err(format!("frame size {} not in 1..={}",
+                               self.frame_size, MAX_FRAME));
+        }
+
+        if self.page_size == 0 {
+            return Err("page size can't be 0".to_string());
+        }
+
+        // room for the exit code, flags, program and at least a frame
+        let need = CODE_OFFSET - MAX_FRAME + code_len + self.frame_size;
+        if self.fast_size < need {
+            return Err(format!("fast size {} too small for program, needs {}",
+                               self.fast_size, need));
+        }
+
+        return Ok(());
+    }
+}
+
+/**
+ * Values that can be loaded from and stored to memory. They are read
+ * straight from the bytes, so it's only implemented for the integer and
+ * float primitives, where every bit pattern is a valid value and there
+ * is no padding. Sealed so it can't be implemented outside this crate.
+ */
+pub trait Scalar: Copy + sealed::Sealed {}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+macro_rules! scalar {
+    ($($t:ty),*) => {
+        $(
+            impl sealed::Sealed for $t {}
+            impl Scalar for $t {}
+        )*
+    }
+}
+
+scalar!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+/**
+ * the bytes of a value
+ */
+pub fn scalar_bytes<T: Scalar>(val: &T) -> &[u8] {
+    return unsafe {
+        std::slice::from_raw_parts(val as *const T as *const u8, size_of::<T>())
+    };
+}
+
+/**
+ * What can be done with a region of memory
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Perm(u8);
+
+impl Perm {
+    pub const NONE: Perm = Perm(0);
+    pub const READ: Perm = Perm(1);
+    pub const WRITE: Perm = Perm(1 << 1);
+    pub const EXEC: Perm = Perm(1 << 2);
+
+    pub const RW: Perm = Perm(Perm::READ.0 | Perm::WRITE.0);
+    pub const RX: Perm = Perm(Perm::READ.0 | Perm::EXEC.0);
+    pub const RWX: Perm = Perm(Perm::RW.0 | Perm::EXEC.0);
+
+    /**
+     * memory outside any protected region can be read and written, but
+     * not executed
+     */
+    pub const DEFAULT: Perm = Perm::RW;
+
+    pub fn contains(self, other: Perm) -> bool {
+        return self.0 & other.0 == other.0;
+    }
+}
+
+impl BitOr for Perm {
+    type Output = Perm;
+
+    fn bitor(self, other: Perm) -> Perm {
+        return Perm(self.0 | other.0);
+    }
+}
 
 pub struct Memory {
-    fast: [u8; FAST_SIZE],
-    page: Vec<Option<[u8; PAGE_SIZE]>>,
+    config: MemoryConfig,
+    fast: Box<[u8]>,
+    page: Vec<Option<Box<[u8]>>>,
+    n_pages: usize, // allocated pages
     sp: usize,
+    code_end: usize, // the stack may not grow past here
+    guard: usize, // bytes past code_end the stack may not grow into
+    devices: Vec<Mapping>,
+    regions: BTreeMap<usize, (usize, Perm)>, // start -> end, disjoint
+}
 
+/**
+ * a device mapped at len bytes from start
+ */
+struct Mapping {
+    start: usize,
+    len: usize,
+    device: RefCell<Box<dyn Device>>, // reads happen through &Memory
 }
 
 impl Memory {
-    pub fn new(code: &[u8]) -> Memory {
+    pub fn new(code: &[u8], config: MemoryConfig) -> Result<Memory, String> {
+        config.validate(code.len())?;
+
         let mut ret = Memory{
-            fast: [0; FAST_SIZE],
+            config,
+            fast: vec![0; config.fast_size].into_boxed_slice(),
             page: vec![],
-            sp: FAST_SIZE - 1,
+            n_pages: 0,
+            sp: config.fast_size - 1,
+            code_end: code.len() + CODE_OFFSET - MAX_FRAME,
+            guard: 0,
+            devices: vec![],
+            regions: BTreeMap::new(),
+        };
+
+        let start = CODE_OFFSET - MAX_FRAME;
+        ret.fast[start..start+code.len()].clone_from_slice(code);
+        ret.protect(CODE_OFFSET, code.len(), Perm::RX);
+
+        return Ok(ret);
+    }
+
+    pub fn config(&self) -> &MemoryConfig {
+        return &self.config;
+    }
+
+    /**
+     * address of the first page, just past fast memory
+     */
+    fn page_base(&self) -> usize {
+        return MAX_FRAME + self.config.fast_size;
+    }
+
+    /**
+     * allocate a zeroed page, faulting once max_pages are in use
+     */
+    pub fn alloc_page(&mut self) -> Result<usize, Fault> {
+        return self.alloc_pages(1);
+    }
+
+    /**
+     * allocate n zeroed pages next to each other, returning the address
+     * of the first
+     */
+    pub fn alloc_pages(&mut self, n: usize) -> Result<usize, Fault> {
+        if n > self.config.max_pages - self.n_pages {
+            return Err(TrapKind::OutOfMemory.into());
+        }
+
+        let mut run = 0;
+        let mut found = None;
+        for (i, item) in self.page.iter().enumerate() {
+            run = if item.is_none() { run + 1 } else { 0 };
+            if run == n {
+                found = Some(i + 1 - n);
+                break;
+            }
+        }
+
+        // otherwise grow the table, reusing any free slots at its end
+        let start = found.unwrap_or(self.page.len() - run);
+        if self.page.len() < start + n {
+            self.page.resize_with(start + n, || None);
+        }
+
+        let page_size = self.config.page_size;
+        for item in &mut self.page[start..start+n] {
+            *item = Some(vec![0; page_size].into_boxed_slice());
+        }
+        self.n_pages += n;
+
+        return Ok(self.page_base() + (start * page_size));
+    }
+
+    pub fn free_page(&mut self, addr:usize) -> Result<(), Fault> {
+        let missing = Fault::new(TrapKind::PageNotExist, addr);
+
+        let i = match addr.checked_sub(self.page_base()) {
+            Some(addr1) => addr1 / self.config.page_size,
+            None => return Err(missing),
         };
 
-        ret.fast[1..code.len()+1].clone_from_slice(code);
+        match self.page.get_mut(i) {
+            Some(item) if item.is_some() => {
+                *item = None;
+                self.n_pages -= 1;
+                return Ok(());
+            },
+            _ => return Err(missing),
+        }
+    }
+
+    /**
+     * set the permissions of the len bytes from start, replacing the
+     * permissions of any region they overlap
+     */
+    pub fn protect(&mut self, start: usize, len: usize, perm: Perm) {
+        if len == 0 {
+            return;
+        }
+
+        let end = start.saturating_add(len);
 
-        return ret;
+        let overlapping: Vec<usize> = self.regions.range(..end)
+            .filter(|(_, &(e, _))| e > start)
+            .map(|(&s, _)| s)
+            .collect();
+
+        // keep the parts of overlapped regions outside start..end
+        for s in overlapping {
+            let (e, p) = self.regions.remove(&s).unwrap();
+            if s < start {
+                self.regions.insert(s, (start, p));
+            }
+            if e > end {
+                self.regions.insert(end, (e, p));
+            }
+        }
+
+        self.regions.insert(start, (end, perm));
     }
 
-    pub fn alloc_page(&mut self) -> usize {
+    /**
+     * the permissions of addr, frame relative addresses past the base of
+     * the stack can't be accessed at all
+     */
+    pub fn perm(&self, addr: usize) -> Perm {
+        let addr = match self.absolute(addr, 1) {
+            Ok(addr) => addr,
+            Err(_) => return Perm::NONE,
+        };
 
-        for (i, item) in self.page.iter_mut().enumerate() {
-            if item.is_none() {
-                item.replace([0; PAGE_SIZE]);
+        match self.regions.range(..=addr).next_back() {
+            Some((_, &(end, perm))) if addr < end => return perm,
+            _ => return Perm::DEFAULT,
+        }
+    }
+
+    /**
+     * fault unless all len bytes from addr allow perm
+     */
+    pub fn check(&self, addr: usize, len: usize, perm: Perm) -> Result<(), Fault> {
+        let start = self.absolute(addr, len)?;
+        let end = start.saturating_add(len);
+        let fault = Fault::new(TrapKind::Protection, addr);
 
-                return MAX_FRAME + FAST_SIZE + (i * PAGE_SIZE);
+        let mut covered = 0;
+        for (&s, &(e, p)) in self.regions.range(..end) {
+            if e <= start {
+                continue;
+            }
+            if !p.contains(perm) {
+                return Err(fault);
             }
+            covered += e.min(end) - s.max(start);
         }
 
-        let i = self.page.len();
-        self.page.push(Some([0; PAGE_SIZE]));
+        if covered < end - start && !Perm::DEFAULT.contains(perm) {
+            return Err(fault);
+        }
 
-        return MAX_FRAME + FAST_SIZE + (i * PAGE_SIZE);
+        return Ok(());
     }
 
-    pub fn free_page(&mut self, addr:usize) {
-        let i = (addr - MAX_FRAME - FAST_SIZE) / PAGE_SIZE;
+    /**
+     * read the op code at addr, which must be executable
+     */
+    pub fn fetch(&self, addr: usize) -> Result<u8, Fault> {
+        self.check(addr, 1, Perm::EXEC)?;
+        return self.get(addr);
+    }
 
-        std::mem::drop(self.page[i].expect("page does not exist"));
+    /**
+     * keep len bytes between the end of the code and the stack which can
+     * not be accessed, so the stack overflows before reaching them. The
+     * guard is cut short at the end of fast memory.
+     */
+    pub fn set_stack_guard(&mut self, len: usize) {
+        let len = len.min(self.config.fast_size - self.code_end);
+        let start = MAX_FRAME + self.code_end;
+        self.protect(start, self.guard.max(len), Perm::DEFAULT);
+        self.protect(start, len, Perm::NONE);
+        self.guard = len;
+    }
 
-        self.page[i] = None;
+    /**
+     * frame relative addresses as the fast memory address they refer to
+     */
+    fn absolute(&self, addr: usize, len: usize) -> Result<usize, Fault> {
+        if addr > 0 && addr < self.config.frame_size {
+            return Ok(MAX_FRAME + self.frame_index(addr, len)?);
+        }
+
+        return Ok(addr);
+    }
+
+    /**
+     * the fast memory index of the len bytes at frame relative addr,
+     * faulting if they run past the base of the stack
+     */
+    fn frame_index(&self, addr: usize, len: usize) -> Result<usize, Fault> {
+        let idx = self.sp + addr;
+        match idx.checked_add(len) {
+            Some(end) if end <= self.config.fast_size => return Ok(idx),
+            _ => return Err(Fault::new(TrapKind::StackUnderflow, addr)),
+        }
+    }
+
+    /**
+     * map a device at the len bytes from start, which must be past the
+     * max_pages pages the program can allocate and not overlap another
+     * device
+     */
+    pub fn map_device(&mut self, start: usize, len: usize,
+                      device: Box<dyn Device>) -> Result<(), String> {
+
+        let end = match start.checked_add(len) {
+            Some(end) if len > 0 => end,
+            _ => return Err(format!("bad device range {} + {}", start, len)),
+        };
+
+        if start < self.page_base() {
+            return Err(format!("device at {} overlaps fast memory", start));
+        }
+
+        let page_end = self.config.max_pages
+            .saturating_mul(self.config.page_size)
+            .saturating_add(self.page_base());
+        if start < page_end {
+            return Err(format!("device at {} overlaps pages, which end at {}",
+                               start, page_end));
+        }
 
+        for mapping in &self.devices {
+            if start < mapping.start + mapping.len && mapping.start < end {
+                return Err(format!("device at {} overlaps device at {}",
+                                   start, mapping.start));
+            }
+        }
+
+        self.devices.push(Mapping{ start, len, device: RefCell::new(device) });
+
+        return Ok(());
     }
 
-    pub fn push_addr(&mut self, addr: usize) {
-        let loc = self.sp;
-        self.set(loc, addr);
-        self.sp -= 8;
+    /**
+     * remove the device mapped at start
+     */
+    pub fn unmap_device(&mut self, start: usize) -> Option<Box<dyn Device>> {
+        let i = self.devices.iter().position(|m| m.start == start)?;
+        return Some(self.devices.remove(i).device.into_inner());
     }
 
-    pub fn pop_addr(&mut self) -> usize {
-        self.sp -= 8;
-        return self.get1(self.sp);
+    fn device_read(&self, i: usize, offset: usize, addr: usize,
+                   buf: &mut [u8]) -> Result<(), Fault> {
+
+        return self.devices[i].device.borrow_mut().read(offset, buf)
+            .map_err(|f| Fault{ addr: f.addr.or(Some(addr)), ..f });
     }
 
-    pub fn get<T: Clone>(&self, addr: usize) -> T {
+    fn device_write(&self, i: usize, offset: usize, addr: usize,
+                    bytes: &[u8]) -> Result<(), Fault> {
+
+        return self.devices[i].device.borrow_mut().write(offset, bytes)
+            .map_err(|f| Fault{ addr: f.addr.or(Some(addr)), ..f });
+    }
+
+    /**
+     * address of the top of the stack, the stack grows down from the end
+     * of fast memory and sp is the highest free byte.
+     */
+    fn stack_top(&self) -> usize {
+        return MAX_FRAME + self.sp + 1;
+    }
+
+    /**
+     * the stack pointer, as the address of the top of the stack
+     */
+    pub fn sp(&self) -> usize {
+        return self.stack_top();
+    }
+
+    /**
+     * push a value onto the stack, faulting if the stack would grow into
+     * the program code
+     */
+    pub fn push<T: Scalar>(&mut self, val: T) -> Result<(), Fault> {
+        let size = size_of::<T>();
+
+        match (self.sp + 1).checked_sub(size) {
+            Some(top) if top >= self.code_end + self.guard => {},
+            _ => return Err(
+                Fault::new(TrapKind::StackOverflow, self.stack_top())),
+        }
+
+        self.sp -= size;
+        return self.set(self.stack_top(), val);
+    }
+
+    /**
+     * pop a value off the stack, faulting if that would pop past the base
+     * of the stack
+     */
+    pub fn pop<T: Scalar>(&mut self) -> Result<T, Fault> {
+        let size = size_of::<T>();
+
+        if self.sp + size >= self.config.fast_size {
+            return Err(Fault::new(TrapKind::StackUnderflow, self.stack_top()));
+        }
+
+        let val = self.get(self.stack_top())?;
+        self.sp += size;
+        return Ok(val);
+    }
+
+    pub fn push_addr(&mut self, addr: usize) -> Result<(), Fault> {
+        return self.push(addr);
+    }
+
+    pub fn pop_addr(&mut self) -> Result<usize, Fault> {
+        return self.pop();
+    }
+
+    /**
+     * the effective address of an address operand, see op_code::Operand
+     */
+    pub fn resolve(&self, raw: usize) -> Result<usize, Fault> {
+        return Operand::resolve(raw, |addr| self.get(addr));
+    }
+
+    /**
+     * find where the size bytes at addr live, faulting if they are not
+     * all in the same chunk of memory
+     */
+    fn locate(&self, addr: usize, size: usize) -> Result<Location, Fault> {
 
         /*
          * addressing scheme:
          * 0                    __ null
          * |
          * |
-         * MAX_FRAME            __ relative addresses
-         * |
+         * MAX_FRAME            __ relative addresses, 1 is the top
+         * |                       of the stack, up to frame_size
          * |
-         * FAST_SIZE+MAX_FRAME  __ fast memory
+         * fast_size+MAX_FRAME  __ fast memory
          * |
          * |
          * :                    __ page memory
          */
-        let addr1: usize; // addres space without relative chunk
-        let ptr: *const u8; // pointer to location we are looking for
-
-        if addr == 0 {
-            panic!("null pointer deref");
+        // addres space without relative chunk
+        let addr1 = if addr == 0 {
+            return Err(Fault::new(TrapKind::NullPointer, addr));
+        } else if addr < self.config.frame_size {
+            self.frame_index(addr, size)?
         } else if addr < MAX_FRAME {
-            addr1 = self.sp - addr;
+            return Err(Fault::new(TrapKind::OutOfBounds, addr));
         } else {
-            addr1 = addr - MAX_FRAME;
+            addr - MAX_FRAME
+        };
+
+        let fast_size = self.config.fast_size;
+        if addr1 < fast_size {
+            if addr1 + size > fast_size {
+                return Err(Fault::new(TrapKind::OutOfBounds, addr));
+            }
+
+            return Ok(Location::Fast(addr1));
         }
 
-        // set the pointer
-        if addr1 < FAST_SIZE {
-            ptr = &self.fast[addr];
-        } else {
-            let addr1 = addr1 - FAST_SIZE;
-            let page_num = addr1 >> 12; // page size bit
-            let page_idx = addr1 & (PAGE_SIZE - 1);
+        for (i, mapping) in self.devices.iter().enumerate() {
+            if addr >= mapping.start && addr - mapping.start < mapping.len {
+                let offset = addr - mapping.start;
+                if offset + size > mapping.len {
+                    return Err(Fault::new(TrapKind::OutOfBounds, addr));
+                }
+
+                return Ok(Location::Device(i, offset));
+            }
+        }
+
+        let addr1 = addr1 - fast_size;
+        let page_num = addr1 / self.config.page_size;
+        let page_idx = addr1 % self.config.page_size;
 
-            ptr = &self.page[page_num]
-                .expect("page not exist")[page_idx];
+        match self.page.get(page_num) {
+            Some(Some(_)) => {},
+            _ => return Err(Fault::new(TrapKind::PageNotExist, addr)),
+        }
+
+        if page_idx + size > self.config.page_size {
+            return Err(Fault::new(TrapKind::OutOfBounds, addr));
+        }
+
+        return Ok(Location::Page(page_num, page_idx));
+    }
+
+    /**
+     * the len bytes at addr, in place. Devices can't be read in place so
+     * they fault.
+     */
+    pub fn bytes(&self, addr: usize, len: usize) -> Result<&[u8], Fault> {
+        self.check(addr, len, Perm::READ)?;
+
+        let loc = self.locate(addr, len)?;
+        return self.chunk(&loc, addr, len);
+    }
+
+    /**
+     * the len bytes at a location in fast memory or a page, devices have
+     * no bytes to borrow so they fault
+     */
+    fn chunk(&self, loc: &Location, addr: usize, len: usize) -> Result<&[u8], Fault> {
+        let bytes = match *loc {
+            Location::Fast(idx) => self.fast.get(idx..idx+len),
+            Location::Page(num, idx) => match self.page.get(num) {
+                Some(Some(page)) => page.get(idx..idx+len),
+                _ => None,
+            },
+            Location::Device(..) => None,
+        };
+
+        return bytes.ok_or_else(|| Fault::new(TrapKind::OutOfBounds, addr));
+    }
+
+    fn chunk_mut(&mut self, loc: &Location, addr: usize,
+                 len: usize) -> Result<&mut [u8], Fault> {
+
+        let bytes = match *loc {
+            Location::Fast(idx) => self.fast.get_mut(idx..idx+len),
+            Location::Page(num, idx) => match self.page.get_mut(num) {
+                Some(Some(page)) => page.get_mut(idx..idx+len),
+                _ => None,
+            },
+            Location::Device(..) => None,
         };
 
-        return unsafe { (*(ptr as *const T)).clone() };
+        return bytes.ok_or_else(|| Fault::new(TrapKind::OutOfBounds, addr));
+    }
+
+    /**
+     * write bytes starting at addr
+     */
+    pub fn set_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<(), Fault> {
+        let len = bytes.len();
+        if len == 0 {
+            return Ok(());
+        }
+
+        self.check(addr, len, Perm::WRITE)?;
+
+        match self.locate(addr, len)? {
+            Location::Device(i, offset) => {
+                self.device_write(i, offset, addr, bytes)?;
+            },
+            loc => self.chunk_mut(&loc, addr, len)?.copy_from_slice(bytes),
+        }
+
+        return Ok(());
+    }
+
+    /**
+     * where addr is and how many of the len bytes from it are in the same
+     * chunk of memory, relative addresses end at frame_size
+     */
+    fn span(&self, addr: usize, len: usize) -> Result<(Location, usize), Fault> {
+        let loc = self.locate(addr, 1)?;
+        let mut n = match loc {
+            Location::Fast(idx) => self.config.fast_size - idx,
+            Location::Page(_, idx) => self.config.page_size - idx,
+            Location::Device(i, offset) => self.devices[i].len - offset,
+        };
+
+        // locate has already checked relative addresses are on the stack,
+        // and fast memory ends at its base
+        if addr < self.config.frame_size {
+            n = n.min(self.config.frame_size - addr);
+        }
+
+        return Ok((loc, n.min(len)));
     }
 
-    pub fn get1(&self, addr: usize) -> usize {
+    /**
+     * fault unless all len bytes from addr are mapped
+     */
+    pub fn check_range(&self, addr: usize, len: usize) -> Result<(), Fault> {
+        let mut done = 0;
+        while done < len {
+            let at = match addr.checked_add(done) {
+                Some(at) => at,
+                None => return Err(Fault::new(TrapKind::OutOfBounds, addr)),
+            };
+            done += self.span(at, len - done)?.1;
+        }
+
+        return Ok(());
+    }
+
+    /**
+     * read the len bytes from addr, which may span chunks of memory
+     */
+    pub fn read_range(&self, addr: usize, len: usize) -> Result<Vec<u8>, Fault> {
+        self.check_range(addr, len)?;
+        self.check(addr, len, Perm::READ)?;
+
+        let mut ret = Vec::with_capacity(len);
+        while ret.len() < len {
+            let at = addr + ret.len();
+            match self.span(at, len - ret.len())? {
+                (Location::Device(i, offset), n) => {
+                    let mut buf = vec![0; n];
+                    self.device_read(i, offset, at, &mut buf)?;
+                    ret.extend_from_slice(&buf);
+                },
+                (_, n) => ret.extend_from_slice(self.bytes(at, n)?),
+            }
+        }
+
+        return Ok(ret);
+    }
+
+    /**
+     * write bytes from addr, which may span chunks of memory. Nothing is
+     * written if any of it is out of range.
+     */
+    pub fn write_range(&mut self, addr: usize, bytes: &[u8]) -> Result<(), Fault> {
+        self.check_range(addr, bytes.len())?;
+        self.check(addr, bytes.len(), Perm::WRITE)?;
+
+        let mut done = 0;
+        while done < bytes.len() {
+            let (_, n) = self.span(addr + done, bytes.len() - done)?;
+            self.set_bytes(addr + done, &bytes[done..done+n])?;
+            done += n;
+        }
+
+        return Ok(());
+    }
+
+    /**
+     * set len bytes from dst to val
+     */
+    pub fn fill(&mut self, dst: usize, val: u8, len: usize) -> Result<(), Fault> {
+        self.check_range(dst, len)?;
+        self.check(dst, len, Perm::WRITE)?;
+
+        let mut done = 0;
+        while done < len {
+            let (loc, n) = self.span(dst + done, len - done)?;
+            match loc {
+                Location::Device(i, offset) => {
+                    self.device_write(i, offset, dst + done, &vec![val; n])?;
+                },
+                loc => self.chunk_mut(&loc, dst + done, n)?.fill(val),
+            }
+            done += n;
+        }
+
+        return Ok(());
+    }
+
+    pub fn get<T: Scalar>(&self, addr: usize) -> Result<T, Fault> {
+        let size = size_of::<T>();
+        self.check(addr, size, Perm::READ)?;
+
+        let mut buf = vec![];
+        let bytes = match self.locate(addr, size)? {
+            Location::Device(i, offset) => {
+                buf.resize(size, 0);
+                self.device_read(i, offset, addr, &mut buf)?;
+                &buf[..]
+            },
+            loc => self.chunk(&loc, addr, size)?,
+        };
+
+        // operands in the code are packed, so reads are not aligned
+        return Ok(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) });
+    }
+
+    pub fn get1(&self, addr: usize) -> Result<usize, Fault> {
         return self.get::<usize>(addr);
     }
 
-    pub fn get2(&self, addr: usize) -> [usize; 2] {
-        return self.get::<[usize; 2]>(addr);
+    pub fn get2(&self, addr: usize) -> Result<[usize; 2], Fault> {
+        return Ok([self.get(addr)?, self.get(addr + 8)?]);
     }
 
-    pub fn set<T: Clone>(&mut self, addr: usize, val: T) {
-        let addr1: usize; // addres space without relative chunk
-        let ptr: *mut u8; // pointer to location we are looking for
+    pub fn set<T: Scalar>(&mut self, addr: usize, val: T) -> Result<(), Fault> {
+        return self.set_bytes(addr, scalar_bytes(&val));
+    }
 
-        if addr == 0 {
-            panic!("null pointer deref");
-        } else if addr < MAX_FRAME {
-            addr1 = self.sp - addr;
-        } else {
-            addr1 = addr - MAX_FRAME;
+    /**
+     * write everything but the devices to a snapshot
+     */
+    pub(crate) fn save(&self, enc: &mut Encoder) -> io::Result<()> {
+        enc.usize(self.config.fast_size)?;
+        enc.usize(self.config.page_size)?;
+        enc.usize(self.config.frame_size)?;
+        enc.usize(self.config.max_pages)?;
+
+        enc.usize(self.sp)?;
+        enc.usize(self.code_end)?;
+        enc.usize(self.guard)?;
+        enc.bytes(&self.fast)?;
+
+        enc.usize(self.page.len())?;
+        for item in &self.page {
+            enc.option(item.as_ref(), |enc, page| enc.bytes(page))?;
         }
 
-        // set the pointer
-        if addr1 < FAST_SIZE {
-            ptr = &mut self.fast[addr];
-        } else {
-            let addr1 = addr1 - FAST_SIZE;
-            let page_num = addr1 >> 12; // page size bit
-            let page_idx = addr1 & (PAGE_SIZE - 1);
+        enc.usize(self.regions.len())?;
+        for (&start, &(end, perm)) in &self.regions {
+            enc.usize(start)?;
+            enc.usize(end)?;
+            enc.u8(perm.0)?;
+        }
 
-            ptr = &mut self.page[page_num]
-                .expect("page not exist")[page_idx];
+        return Ok(());
+    }
+
+    /**
+     * replace everything but the devices with a snapshot written by save
+     */
+    pub(crate) fn restore(&mut self, dec: &mut Decoder) -> io::Result<()> {
+        let config = MemoryConfig{
+            fast_size: dec.usize()?,
+            page_size: dec.usize()?,
+            frame_size: dec.usize()?,
+            max_pages: dec.usize()?,
         };
+        config.validate(0).map_err(invalid)?;
 
-        unsafe {
-            *(ptr as *mut T) = val.clone()
+        let page_base = match MAX_FRAME.checked_add(config.fast_size) {
+            Some(base) => base,
+            None => return Err(invalid(format!("bad fast size {}", config.fast_size))),
         };
+        let page_end = config.max_pages
+            .saturating_mul(config.page_size)
+            .saturating_add(page_base);
+        if self.devices.iter().any(|m| m.start < page_base) {
+            return Err(invalid("a device overlaps fast memory".to_string()));
+        }
+        if self.devices.iter().any(|m| m.start < page_end) {
+            return Err(invalid("a device overlaps pages".to_string()));
+        }
+
+        let sp = dec.usize()?;
+        let code_end = dec.usize()?;
+        let guard = dec.usize()?;
+        if sp >= config.fast_size || code_end > config.fast_size {
+            return Err(invalid(format!("bad stack pointer {}", sp)));
+        }
+        match code_end.checked_add(guard) {
+            Some(end) if end <= config.fast_size => {},
+            _ => return Err(invalid(format!("bad stack guard {}", guard))),
+        }
+
+        let fast = dec.bytes(config.fast_size)?.into_boxed_slice();
+
+        let mut page = vec![];
+        for _ in 0..dec.usize()? {
+            page.push(dec.option(|dec| {
+                return Ok(dec.bytes(config.page_size)?.into_boxed_slice());
+            })?);
+        }
+
+        // regions are saved in order, so each must start after the last
+        let mut regions = BTreeMap::new();
+        let mut last_end = 0;
+        for _ in 0..dec.usize()? {
+            let start = dec.usize()?;
+            let end = dec.usize()?;
+            let perm = Perm(dec.u8()?);
+            if !Perm::RWX.contains(perm) || end <= start || start < last_end {
+                return Err(invalid(format!("bad region at {}", start)));
+            }
+            regions.insert(start, (end, perm));
+            last_end = end;
+        }
+
+        let n_pages = page.iter().filter(|item| item.is_some()).count();
+        if n_pages > config.max_pages {
+            return Err(invalid(format!("{} pages, more than the limit of {}",
+                                       n_pages, config.max_pages)));
+        }
+
+        self.n_pages = n_pages;
+        self.config = config;
+        self.fast = fast;
+        self.page = page;
+        self.sp = sp;
+        self.code_end = code_end;
+        self.guard = guard;
+        self.regions = regions;
+
+        return Ok(());
     }
 }
+
+/**
+ * where an address ended up after translating it
+ */
+enum Location {
+    Fast(usize), // index into fast memory
+    Page(usize, usize), // page number and index into the page
+    Device(usize, usize), // device number and offset into it
+}
@@ -1,415 +1,156 @@
-mod op_code;
-use op_code::Op;
-
-mod memory;
-use memory::*;
-
-fn eval(code: &[u8]) -> u8 {
-    let mut memory = Memory::new(code);
-
-    // registers
-    let mut pc: usize = 1;
-
-    loop {
-
-        let op:u8 = memory.get(pc);
-        println!("{:?}", Op::from_int(op));
-        pc += 1;
-
-        match Op::from_int(op) {
-            Op::NOP => {},// nop
-            Op::XIT => {
-                return memory.get::<u8>(memory::PROG_OFFSET);
-            },
-
-            //
-            // Integer arithmetic
-            //
-
-            Op::ADD1 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(pc);
-                pc += 1;
-                memory.set(dst, dstv + srcv);
-            },
-            Op::ADD2 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv + srcv);
-            },
-            Op::ADD3 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u64 = memory.get(dst);
-                let srcv: u64 = memory.get(pc);
-                pc += 8;
-                memory.set(dst, dstv + srcv);
-            },
-            Op::ADD4 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv + srcv);
-            },
-
-            Op::SUB1 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(pc);
-                pc += 1;
-                memory.set(dst, dstv - srcv);
-            },
-            Op::SUB2 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv - srcv);
-            },
-            Op::SUB3 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u64 = memory.get(dst);
-                let srcv: u64 = memory.get(pc);
-                pc += 8;
-                memory.set(dst, dstv - srcv);
-            },
-            Op::SUB4 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv - srcv);
-            },
-
-            Op::MUL1 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(pc);
-                pc += 1;
-                memory.set(dst, dstv * srcv);
-            },
-            Op::MUL2 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv * srcv);
-            },
-            Op::MUL3 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u64 = memory.get(dst);
-                let srcv: u64 = memory.get(pc);
-                pc += 8;
-                memory.set(dst, dstv * srcv);
-            },
-            Op::MUL4 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv * srcv);
-            },
-
-            Op::DIV1 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(pc);
-                pc += 1;
-                memory.set(dst, dstv / srcv);
-            },
-            Op::DIV2 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv / srcv);
-            },
-            Op::DIV3 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u64 = memory.get(dst);
-                let srcv: u64 = memory.get(pc);
-                pc += 8;
-                memory.set(dst, dstv / srcv);
-            },
-            Op::DIV4 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv / srcv);
-            },
-
-            Op::MOD1 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(pc);
-                pc += 1;
-                memory.set(dst, dstv % srcv);
-            },
-            Op::MOD2 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv % srcv);
-            },
-            Op::MOD3 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u64 = memory.get(dst);
-                let srcv: u64 = memory.get(pc);
-                pc += 8;
-                memory.set(dst, dstv % srcv);
-            },
-            Op::MOD4 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv % srcv);
-            },
-
-            Op::SHR1 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(pc);
-                pc += 1;
-                memory.set(dst, dstv >> srcv);
-            },
-            Op::SHR2 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv >> srcv);
-            },
-            Op::SHR3 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u64 = memory.get(dst);
-                let srcv: u64 = memory.get(pc);
-                pc += 8;
-                memory.set(dst, dstv >> srcv);
-            },
-            Op::SHR4 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv >> srcv);
-            },
-
-            Op::SHL1 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(pc);
-                pc += 1;
-                memory.set(dst, dstv << srcv);
-            },
-            Op::SHL2 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv << srcv);
-            },
-            Op::SHL3 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u64 = memory.get(dst);
-                let srcv: u64 = memory.get(pc);
-                pc += 8;
-                memory.set(dst, dstv << srcv);
-            },
-            Op::SHL4 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv << srcv);
-            },
-
-
-            Op::AND1 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(pc);
-                pc += 1;
-                memory.set(dst, dstv & srcv);
-            },
-            Op::AND2 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv & srcv);
-            },
-            Op::AND3 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u64 = memory.get(dst);
-                let srcv: u64 = memory.get(pc);
-                pc += 8;
-                memory.set(dst, dstv & srcv);
-            },
-            Op::AND4 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv & srcv);
-            },
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+use mvm::{MemoryConfig, Tracer, Vm};
+use mvm::asm;
+use mvm::debugger::Debugger;
+use mvm::trace::{JsonTracer, TextTracer};
+
+const USAGE: &str = "\
+usage: mvm [--trace | --trace-json] [--fuel n] [--guard n] [--save file]
+           [--restore] <program>
+       mvm debug <program>
+
+--save writes a snapshot if the program traps or runs out of fuel, and
+--restore runs a snapshot instead of a program.";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
 
-            Op::ORR1 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(pc);
-                pc += 1;
-                memory.set(dst, dstv | srcv);
-            },
-            Op::ORR2 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv | srcv);
-            },
-            Op::ORR3 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u64 = memory.get(dst);
-                let srcv: u64 = memory.get(pc);
-                pc += 8;
-                memory.set(dst, dstv | srcv);
-            },
-            Op::ORR4 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv | srcv);
-            },
+fn read_program(path: &str) -> Vec<u8> {
+    match fs::read(path) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("could not read {}: {}", path, err);
+            process::exit(2);
+        },
+    }
+}
 
-            Op::XOR1 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(pc);
-                pc += 1;
-                memory.set(dst, dstv ^ srcv);
-            },
-            Op::XOR2 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv ^ srcv);
-            },
-            Op::XOR3 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let dstv: u64 = memory.get(dst);
-                let srcv: u64 = memory.get(pc);
-                pc += 8;
-                memory.set(dst, dstv ^ srcv);
-            },
-            Op::XOR4 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let dstv: u8 = memory.get(dst);
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, dstv ^ srcv);
-            },
+/**
+ * a vm running code, exiting if it doesn't fit in memory
+ */
+fn load(path: &str, code: &[u8]) -> Vm {
+    match Vm::with_config(code, MemoryConfig::default()) {
+        Ok(vm) => vm,
+        Err(msg) => {
+            eprintln!("could not load {}: {}", path, msg);
+            process::exit(2);
+        },
+    }
+}
 
-            //
-            // floating point ops
-            //
+/**
+ * debug a program, using the labels in the symbol file next to it if
+ * the assembler wrote one
+ */
+fn debug(path: &str) {
+    let vm = load(path, &read_program(path));
+
+    let sym_path = Path::new(path).with_extension("sym");
+    let labels = match fs::read_to_string(&sym_path) {
+        Ok(src) => match asm::parse_symbols(&src) {
+            Ok(labels) => labels,
+            Err(msg) => {
+                eprintln!("{}: {}", sym_path.display(), msg);
+                process::exit(2);
+            },
+        },
+        Err(_) => Default::default(),
+    };
+
+    let stdin = io::stdin();
+    let mut debugger = Debugger::new(vm, labels);
+    if let Err(err) = debugger.run(stdin.lock(), io::stdout()) {
+        eprintln!("{}", err);
+        process::exit(2);
+    }
+}
 
-            Op::ADDF => {
-                let [a, b] = memory.get2(pc);
-                let aa: f32 = memory.get(a);
-                let bb: f32 = memory.get(b);
-                memory.set(a, aa + bb);
-            },
-            Op::SUBF => {
-                let [a, b] = memory.get2(pc);
-                let aa: f32 = memory.get(a);
-                let bb: f32 = memory.get(b);
-                memory.set(a, aa - bb);
-            },
-            Op::MULF => {
-                let [a, b] = memory.get2(pc);
-                let aa: f32 = memory.get(a);
-                let bb: f32 = memory.get(b);
-                memory.set(a, aa * bb);
-            },
-            Op::DIVF => {
-                let [a, b] = memory.get2(pc);
-                let aa: f32 = memory.get(a);
-                let bb: f32 = memory.get(b);
-                memory.set(a, aa / bb);
-            },
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
 
-            //
-            // memory operations
-            //
+    if let [cmd, path] = args.as_slice() {
+        if cmd == "debug" {
+            debug(path);
+            return;
+        }
+    }
 
-            Op::CPY1 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let srcv: u8 = memory.get(pc);
-                pc += 1;
-                memory.set(dst, srcv);
-            },
-            Op::CPY2 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, srcv);
-            },
-            Op::CPY3 => {
-                let dst: usize = memory.get(pc);
-                pc += 8;
-                let srcv: u64 = memory.get(pc);
-                pc += 8;
-                memory.set(dst, srcv);
-            },
-            Op::CPY4 => {
-                let [dst, src] = memory.get2(pc);
-                pc += 16;
-                let srcv: u8 = memory.get(src);
-                memory.set(dst, srcv);
-            },
+    let mut path = None;
+    let mut tracer: Option<Box<dyn Tracer>> = None;
+    let mut fuel = None;
+    let mut guard = None;
+    let mut save = None;
+    let mut restore = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // traces go to stderr so they don't mix with the program's output
+            "--trace" => tracer = Some(Box::new(TextTracer::new(io::stderr()))),
+            "--trace-json" => tracer = Some(Box::new(JsonTracer::new(io::stderr()))),
+            "--fuel" => match args.next().map(|n| n.parse::<u64>()) {
+                Some(Ok(n)) => fuel = Some(n),
+                _ => usage(),
+            },
+            "--guard" => match args.next().map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) => guard = Some(n),
+                _ => usage(),
+            },
+            "--save" => match args.next() {
+                Some(file) => save = Some(file),
+                None => usage(),
+            },
+            "--restore" => restore = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => usage(),
+        }
+    }
 
-            code => panic!(format!("{:?} not implemented", code)),
+    let path = match path {
+        Some(path) => path,
+        None => usage(),
+    };
 
+    let mut vm = if restore {
+        let mut vm = load(&path, &[]);
+        if let Err(err) = vm.restore(read_program(&path).as_slice()) {
+            eprintln!("could not restore {}: {}", path, err);
+            process::exit(2);
         }
-    }
+        vm
+    } else {
+        load(&path, &read_program(&path))
+    };
 
-    println!("{:?}", memory.get::<[u8; 32]>(0));
-}
+    if let Some(tracer) = tracer {
+        vm.set_tracer(tracer);
+    }
+    if let Some(fuel) = fuel {
+        vm.set_fuel(fuel);
+    }
+    if let Some(guard) = guard {
+        vm.memory_mut().set_stack_guard(guard);
+    }
 
-fn main() {
-    let s = eval(&[
-                    Op::ADD1 as u8, 64, 0, 0, 0, 0, 0, 0, 0, 10,
-                    Op::MOD1 as u8, 64, 0, 0, 0, 0, 0, 0, 0, 7,
-                    Op::XIT as u8]);
-    println!("{}", s);
-    std::process::exit(s as i32);
+    match vm.run() {
+        Ok(s) => process::exit(s as i32),
+        Err(trap) => {
+            eprintln!("trap: {}", trap);
+            if let Some(file) = save {
+                let res = fs::File::create(&file)
+                    .and_then(|f| vm.snapshot(io::BufWriter::new(f)));
+                if let Err(err) = res {
+                    eprintln!("could not save {}: {}", file, err);
+                }
+            }
+            process::exit(1);
+        },
+    }
 }
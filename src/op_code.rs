@@ -1,78 +1,94 @@
-macro_rules! dense_enum {
-    ($name:ident;
-        $($var:ident) , * ,
-    ) => {
-        #[allow(dead_code)]
-        #[derive(Debug)]
-        pub enum $name {
-            $($var) , *
-        }
+use std::fmt;
 
-        impl $name {
-            pub fn from_int(i:u8) -> $name {
-                return unsafe { std::mem::transmute::<u8, $name>(i) }
-            }
-
-            pub fn to_str(&self) -> &'static str {
-                match self {
-                    $($name::$var => stringify!($var)) , *
-                }
-            }
-
-            pub fn from_string(s: String) -> Result<$name, String> {
-                match s.to_uppercase().as_str() {
-                    $(stringify!($var) => Ok($name::$var)) , * ,
-                    _ => Err(format!("{} not a[n] {}", s, stringify!($name))),
-                }
-            }
-        }
-    }
-}
+use crate::alu::{FloatOp, IntOp};
+use crate::trap::TrapKind;
 
 /*
  * 1 -> 1 byte       left is ptr, right is val
  * 2 -> 1 byte       left + right are ptrs
  * 3 -> 8 bytes      left is ptr, right is val
  * 4 -> 8 bytes      left + right are ptrs
+ * 5 -> 2 bytes      left is ptr, right is val
+ * 6 -> 2 bytes      left + right are ptrs
+ * 7 -> 4 bytes      left is ptr, right is val
+ * 8 -> 4 bytes      left + right are ptrs
  */
 dense_enum! { Op;
     // misc
     NOP, XIT,
 
     // integers
-    ADD1, ADD2, ADD3, ADD4,
-    SUB1, SUB2, SUB3, SUB4,
-    MUL1, MUL2, MUL3, MUL4,
-    DIV1, DIV2, DIV3, DIV4,
-    MOD1, MOD2, MOD3, MOD4,
+    ADD1, ADD2, ADD3, ADD4, ADD5, ADD6, ADD7, ADD8,
+    SUB1, SUB2, SUB3, SUB4, SUB5, SUB6, SUB7, SUB8,
+    MUL1, MUL2, MUL3, MUL4, MUL5, MUL6, MUL7, MUL8,
+    DIV1, DIV2, DIV3, DIV4, DIV5, DIV6, DIV7, DIV8,
+    MOD1, MOD2, MOD3, MOD4, MOD5, MOD6, MOD7, MOD8,
     // bitwise shift
-    SHR1, SHR2, SHR3, SHR4,
-    SHL1, SHL2, SHL3, SHL4,
+    SHR1, SHR2, SHR3, SHR4, SHR5, SHR6, SHR7, SHR8,
+    SHL1, SHL2, SHL3, SHL4, SHL5, SHL6, SHL7, SHL8,
     // bitwise ops
-    AND1, AND2, AND3, AND4,
-    ORR1, ORR2, ORR3, ORR4,
-    XOR1, XOR2, XOR3, XOR4,
+    AND1, AND2, AND3, AND4, AND5, AND6, AND7, AND8,
+    ORR1, ORR2, ORR3, ORR4, ORR5, ORR6, ORR7, ORR8,
+    XOR1, XOR2, XOR3, XOR4, XOR5, XOR6, XOR7, XOR8,
+    // signed division and arithmetic shift
+    DVS1, DVS2, DVS3, DVS4, DVS5, DVS6, DVS7, DVS8,
+    MDS1, MDS2, MDS3, MDS4, MDS5, MDS6, MDS7, MDS8,
+    SAR1, SAR2, SAR3, SAR4, SAR5, SAR6, SAR7, SAR8,
 
-    // floats
-    ADDF, SUBF, MULF, DIVF,
+    // floats, 1 and 2 are f32 and 3 and 4 are f64. CMF only sets flags
+    ADF1, ADF2, ADF3, ADF4,
+    SBF1, SBF2, SBF3, SBF4,
+    MLF1, MLF2, MLF3, MLF4,
+    DVF1, DVF2, DVF3, DVF4,
+    CMF1, CMF2, CMF3, CMF4,
+    // compare, writing 1 or 0 to dst, followed by a Cond byte
+    CPI1, CPI2, CPI3, CPI4, CPI5, CPI6, CPI7, CPI8,
+    CPF1, CPF2, CPF3, CPF4,
+    // conversions, 1 is f32 and 2 is f64, integers are 8 byte signed
+    ITF1, ITF2, // integer to float
+    FTI1, FTI2, // float to integer, truncating
+    FRI1, FRI2, // float to integer, rounding half away from 0
+    FEX, FNR, // f32 to f64, f64 to f32
 
     // copy pointed to
-    CPY1, CPY2, CPY3, CPY4,
+    CPY1, CPY2, CPY3, CPY4, CPY5, CPY6, CPY7, CPY8,
+
+    // block memory, 1: 8 byte len, 2: len is at an address
+    MCP1, MCP2, // copy dst src len, the ranges may overlap
+    MST1, MST2, // set dst byte len
+    MCM1, MCM2, // compare a b len, setting flags like CMF
 
     // turing completenes
     JMP1, JMP2, // 1: static address, 2: dynamic address
     JIT, CAL, RET,
+    // jump if any flag in the mask is set, or if they are all clear
+    JFS, JFC,
 
     // stack
-    PSH1, PSH2, PSH3, PSH4,
-    POP1, POP2, POP3, POP4,
+    PSH1, PSH2, PSH3, PSH4, PSH5, PSH6, PSH7, PSH8,
+    POP1, POP2, POP3, POP4, POP5, POP6, POP7, POP8,
+
+    // call the host function numbered by the 1 byte argument
+    SYS,
 
     // extension codes
     EXT,
 }
 
-#[allow(dead_code)]
-pub enum OpExt {
+/*
+ * extension codes, the byte following EXT
+ *
+ * SLP ms       sleep for an 8 byte number of milliseconds
+ * APG dst      allocate a page, its address is written to dst
+ * FPG src      free the page whose address is stored at src
+ * ASY          not implemented
+ * CMT len ...  skip an 8 byte len and len bytes after it
+ * ALC1 dst len allocate an 8 byte len of heap, its address is written
+ *              to dst
+ * ALC2 dst src allocate as many bytes of heap as the word at src
+ * FRE src      free the heap block whose address is stored at src
+ */
+dense_enum! { OpExt;
     // sleep
     SLP,
 
@@ -83,6 +99,420 @@ pub enum OpExt {
     ASY,
 
     // comment, possibly for debug info
-    CMT
+    CMT,
+
+    // heap alloc/free
+    ALC1, ALC2, FRE,
+}
+
+/*
+ * comparison conditions, the byte following CPI and CPF
+ *
+ * CPI cond dst a b     write 1 to the byte at dst if a cond b, else 0
+ *
+ * the S conditions compare integers as signed, they are the same as the
+ * unsigned ones for floats
+ */
+dense_enum! { Cond;
+    EQ, NE,
+    LT, LE, GT, GE,
+    LTS, LES, GTS, GES,
+}
+
+pub const BLOCK_SIZE: u64 = 64;
+pub const BLOCK_COST: u64 = 1;
+pub const SLEEP_COST: u64 = 1; // fuel per millisecond slept
+
+/*
+ * address operands, the top 2 bits are the mode
+ *
+ * 00 direct        the low 62 bits are the address
+ * 01 indirect      the word stored at base, plus offset
+ * 10 indexed       base plus the word stored at index
+ *
+ * base is the low 32 bits and offset or index the 30 bits above it
+ */
+const MODE_SHIFT: u32 = 62;
+const FIELD_SHIFT: u32 = 32;
+const BASE_MASK: usize = (1 << FIELD_SHIFT) - 1;
+const FIELD_MASK: usize = (1 << (MODE_SHIFT - FIELD_SHIFT)) - 1;
+
+/**
+ * A decoded address operand
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Direct(usize),
+    Indirect(usize, usize), // base, offset
+    Indexed(usize, usize), // base, index
+}
+
+impl Operand {
+    /**
+     * None if the mode is not valid
+     */
+    pub fn decode(raw: usize) -> Option<Operand> {
+        let base = raw & BASE_MASK;
+        let field = (raw >> FIELD_SHIFT) & FIELD_MASK;
+
+        match raw >> MODE_SHIFT {
+            0 => return Some(Operand::Direct(raw)),
+            1 => return Some(Operand::Indirect(base, field)),
+            2 => return Some(Operand::Indexed(base, field)),
+            _ => return None,
+        }
+    }
+
+    /**
+     * None if the fields do not fit
+     */
+    pub fn encode(&self) -> Option<usize> {
+        let (mode, base, field) = match *self {
+            Operand::Direct(addr) if addr >> MODE_SHIFT == 0 => return Some(addr),
+            Operand::Direct(_) => return None,
+            Operand::Indirect(base, offset) => (1, base, offset),
+            Operand::Indexed(base, index) => (2, base, index),
+        };
+
+        if base > BASE_MASK || field > FIELD_MASK {
+            return None;
+        }
+
+        return Some(mode << MODE_SHIFT | field << FIELD_SHIFT | base);
+    }
+
+    /**
+     * the effective address of a raw operand, reading the words it points
+     * at with load
+     */
+    pub fn resolve<E, F>(raw: usize, mut load: F) -> Result<usize, E>
+        where E: From<TrapKind>, F: FnMut(usize) -> Result<usize, E> {
+
+        match Operand::decode(raw) {
+            Some(Operand::Direct(addr)) => return Ok(addr),
+            Some(Operand::Indirect(base, offset)) => {
+                let ptr = load(base)?;
+                return Ok(ptr.wrapping_add(offset));
+            },
+            Some(Operand::Indexed(base, index)) => {
+                let i = load(index)?;
+                return Ok(base.wrapping_add(i));
+            },
+            None => return Err(TrapKind::IllegalInstruction.into()),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Direct(addr) => write!(f, "&{}", addr),
+            Operand::Indirect(base, 0) => write!(f, "[&{}]", base),
+            Operand::Indirect(base, offset) => write!(f, "[&{}]+{}", base, offset),
+            Operand::Indexed(base, index) => write!(f, "&{}+[&{}]", base, index),
+        }
+    }
+}
+
+/**
+ * An argument following an op code in the instruction stream
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arg {
+    Addr, // 8 byte address operand
+    Val(usize), // value of n bytes
+    Float(usize), // f32 or f64 value of n bytes
+    Cond, // 1 byte comparison condition
 }
 
+impl Op {
+    /**
+     * the arguments following the op code, EXT is followed by an OpExt
+     * and its arguments
+     */
+    pub fn args(&self) -> &'static [Arg] {
+        match self {
+            Op::NOP | Op::XIT | Op::RET |
+            Op::POP1 | Op::POP3 | Op::POP5 | Op::POP7 |
+            Op::EXT => &[],
+
+            Op::ADD1 | Op::SUB1 | Op::MUL1 | Op::DIV1 | Op::MOD1 |
+            Op::SHR1 | Op::SHL1 |
+            Op::AND1 | Op::ORR1 | Op::XOR1 |
+            Op::DVS1 | Op::MDS1 | Op::SAR1 |
+            Op::CPY1 => &[Arg::Addr, Arg::Val(1)],
+
+            Op::ADD3 | Op::SUB3 | Op::MUL3 | Op::DIV3 | Op::MOD3 |
+            Op::SHR3 | Op::SHL3 |
+            Op::AND3 | Op::ORR3 | Op::XOR3 |
+            Op::DVS3 | Op::MDS3 | Op::SAR3 |
+            Op::CPY3 => &[Arg::Addr, Arg::Val(8)],
+
+            Op::ADD5 | Op::SUB5 | Op::MUL5 | Op::DIV5 | Op::MOD5 |
+            Op::SHR5 | Op::SHL5 |
+            Op::AND5 | Op::ORR5 | Op::XOR5 |
+            Op::DVS5 | Op::MDS5 | Op::SAR5 |
+            Op::CPY5 => &[Arg::Addr, Arg::Val(2)],
+
+            Op::ADD7 | Op::SUB7 | Op::MUL7 | Op::DIV7 | Op::MOD7 |
+            Op::SHR7 | Op::SHL7 |
+            Op::AND7 | Op::ORR7 | Op::XOR7 |
+            Op::DVS7 | Op::MDS7 | Op::SAR7 |
+            Op::CPY7 => &[Arg::Addr, Arg::Val(4)],
+
+            Op::ADD2 | Op::SUB2 | Op::MUL2 | Op::DIV2 | Op::MOD2 |
+            Op::SHR2 | Op::SHL2 |
+            Op::AND2 | Op::ORR2 | Op::XOR2 |
+            Op::DVS2 | Op::MDS2 | Op::SAR2 |
+            Op::CPY2 |
+            Op::ADD4 | Op::SUB4 | Op::MUL4 | Op::DIV4 | Op::MOD4 |
+            Op::SHR4 | Op::SHL4 |
+            Op::AND4 | Op::ORR4 | Op::XOR4 |
+            Op::DVS4 | Op::MDS4 | Op::SAR4 |
+            Op::CPY4 |
+            Op::ADD6 | Op::SUB6 | Op::MUL6 | Op::DIV6 | Op::MOD6 |
+            Op::SHR6 | Op::SHL6 |
+            Op::AND6 | Op::ORR6 | Op::XOR6 |
+            Op::DVS6 | Op::MDS6 | Op::SAR6 |
+            Op::CPY6 |
+            Op::ADD8 | Op::SUB8 | Op::MUL8 | Op::DIV8 | Op::MOD8 |
+            Op::SHR8 | Op::SHL8 |
+            Op::AND8 | Op::ORR8 | Op::XOR8 |
+            Op::DVS8 | Op::MDS8 | Op::SAR8 |
+            Op::CPY8 |
+            Op::ADF2 | Op::SBF2 | Op::MLF2 | Op::DVF2 | Op::CMF2 |
+            Op::ADF4 | Op::SBF4 | Op::MLF4 | Op::DVF4 | Op::CMF4 |
+            Op::ITF1 | Op::ITF2 | Op::FTI1 | Op::FTI2 | Op::FRI1 | Op::FRI2 |
+            Op::FEX | Op::FNR |
+            Op::JIT => &[Arg::Addr, Arg::Addr],
+
+            Op::ADF1 | Op::SBF1 | Op::MLF1 | Op::DVF1 | Op::CMF1 => {
+                &[Arg::Addr, Arg::Float(4)]
+            },
+            Op::ADF3 | Op::SBF3 | Op::MLF3 | Op::DVF3 | Op::CMF3 => {
+                &[Arg::Addr, Arg::Float(8)]
+            },
+
+            Op::JMP1 | Op::JMP2 | Op::CAL |
+            Op::PSH2 | Op::PSH4 | Op::PSH6 | Op::PSH8 |
+            Op::POP2 | Op::POP4 | Op::POP6 | Op::POP8 => &[Arg::Addr],
+
+            Op::JFS | Op::JFC => &[Arg::Addr, Arg::Val(1)],
+
+            Op::CPI1 => &[Arg::Cond, Arg::Addr, Arg::Addr, Arg::Val(1)],
+            Op::CPI3 => &[Arg::Cond, Arg::Addr, Arg::Addr, Arg::Val(8)],
+            Op::CPI5 => &[Arg::Cond, Arg::Addr, Arg::Addr, Arg::Val(2)],
+            Op::CPI7 => &[Arg::Cond, Arg::Addr, Arg::Addr, Arg::Val(4)],
+            Op::CPF1 => &[Arg::Cond, Arg::Addr, Arg::Addr, Arg::Float(4)],
+            Op::CPF3 => &[Arg::Cond, Arg::Addr, Arg::Addr, Arg::Float(8)],
+            Op::CPI2 | Op::CPI4 | Op::CPI6 | Op::CPI8 |
+            Op::CPF2 | Op::CPF4 => &[Arg::Cond, Arg::Addr, Arg::Addr, Arg::Addr],
+
+            Op::MCP1 | Op::MCM1 => &[Arg::Addr, Arg::Addr, Arg::Val(8)],
+            Op::MCP2 | Op::MCM2 => &[Arg::Addr, Arg::Addr, Arg::Addr],
+            Op::MST1 => &[Arg::Addr, Arg::Val(1), Arg::Val(8)],
+            Op::MST2 => &[Arg::Addr, Arg::Val(1), Arg::Addr],
+
+            Op::PSH1 | Op::SYS => &[Arg::Val(1)],
+            Op::PSH3 => &[Arg::Val(8)],
+            Op::PSH5 => &[Arg::Val(2)],
+            Op::PSH7 => &[Arg::Val(4)],
+        }
+    }
+
+    /**
+     * fuel used executing the op code, roughly how expensive it is for
+     * the host. EXT also uses the cost of its extension code, block
+     * memory op codes also use BLOCK_COST for every BLOCK_SIZE bytes and
+     * SLP uses SLEEP_COST for every millisecond.
+     */
+    pub fn cost(&self) -> u64 {
+        match self {
+            Op::MUL1 | Op::MUL2 | Op::MUL3 | Op::MUL4 |
+            Op::MUL5 | Op::MUL6 | Op::MUL7 | Op::MUL8 |
+            Op::ADF1 | Op::ADF2 | Op::ADF3 | Op::ADF4 |
+            Op::SBF1 | Op::SBF2 | Op::SBF3 | Op::SBF4 |
+            Op::MLF1 | Op::MLF2 | Op::MLF3 | Op::MLF4 |
+            Op::CAL | Op::RET => 2,
+
+            Op::DIV1 | Op::DIV2 | Op::DIV3 | Op::DIV4 |
+            Op::DIV5 | Op::DIV6 | Op::DIV7 | Op::DIV8 |
+            Op::MOD1 | Op::MOD2 | Op::MOD3 | Op::MOD4 |
+            Op::MOD5 | Op::MOD6 | Op::MOD7 | Op::MOD8 |
+            Op::DVS1 | Op::DVS2 | Op::DVS3 | Op::DVS4 |
+            Op::DVS5 | Op::DVS6 | Op::DVS7 | Op::DVS8 |
+            Op::MDS1 | Op::MDS2 | Op::MDS3 | Op::MDS4 |
+            Op::MDS5 | Op::MDS6 | Op::MDS7 | Op::MDS8 |
+            Op::DVF1 | Op::DVF2 | Op::DVF3 | Op::DVF4 |
+            Op::MCP1 | Op::MCP2 | Op::MST1 | Op::MST2 | Op::MCM1 | Op::MCM2 |
+            Op::SYS => 4,
+
+            _ => 1,
+        }
+    }
+
+    /**
+     * the integer op, operand width and whether the source is a value,
+     * for op codes in the integer families, see the table above Op
+     */
+    pub fn int_op(&self) -> Option<(IntOp, usize, bool)> {
+        const FAMILIES: [IntOp; 13] = [
+            IntOp::Add, IntOp::Sub, IntOp::Mul, IntOp::Div, IntOp::Mod,
+            IntOp::Shr, IntOp::Shl,
+            IntOp::And, IntOp::Orr, IntOp::Xor,
+            IntOp::Dvs, IntOp::Mds, IntOp::Sar,
+        ];
+
+        let i = (*self as usize).checked_sub(Op::ADD1 as usize)?;
+        let family = *FAMILIES.get(i / 8)?;
+        let (width, imm) = int_variant(i);
+
+        return Some((family, width, imm));
+    }
+
+    /**
+     * the operand width and whether b is a value, for CPI op codes
+     */
+    pub fn int_compare(&self) -> Option<(usize, bool)> {
+        let i = (*self as usize).checked_sub(Op::CPI1 as usize)?;
+        if i >= 8 {
+            return None;
+        }
+
+        return Some(int_variant(i));
+    }
+
+    /**
+     * the operand width and whether b is a value, for CPF op codes
+     */
+    pub fn float_compare(&self) -> Option<(usize, bool)> {
+        let i = (*self as usize).checked_sub(Op::CPF1 as usize)?;
+        if i >= 4 {
+            return None;
+        }
+
+        return Some(float_variant(i));
+    }
+
+    /**
+     * the float op, operand width and whether the source is a value, for
+     * op codes in the float families
+     */
+    pub fn float_op(&self) -> Option<(FloatOp, usize, bool)> {
+        const FAMILIES: [FloatOp; 5] = [
+            FloatOp::Add, FloatOp::Sub, FloatOp::Mul, FloatOp::Div,
+            FloatOp::Cmp,
+        ];
+
+        let i = (*self as usize).checked_sub(Op::ADF1 as usize)?;
+        let family = *FAMILIES.get(i / 4)?;
+        let (width, imm) = float_variant(i);
+
+        return Some((family, width, imm));
+    }
+}
+
+/**
+ * width and whether the source is a value for the i-th op code in an
+ * integer family, see the table above Op
+ */
+fn int_variant(i: usize) -> (usize, bool) {
+    match i % 8 {
+        0 => return (1, true),
+        1 => return (1, false),
+        2 => return (8, true),
+        3 => return (8, false),
+        4 => return (2, true),
+        5 => return (2, false),
+        6 => return (4, true),
+        _ => return (4, false),
+    }
+}
+
+/**
+ * width and whether the source is a value for the i-th op code in a float
+ * family, 1 and 2 are f32 and 3 and 4 are f64
+ */
+fn float_variant(i: usize) -> (usize, bool) {
+    match i % 4 {
+        0 => return (4, true),
+        1 => return (4, false),
+        2 => return (8, true),
+        _ => return (8, false),
+    }
+}
+
+impl OpExt {
+    pub fn cost(&self) -> u64 {
+        match self {
+            OpExt::APG => 64,
+            OpExt::FPG => 16,
+            OpExt::ALC1 | OpExt::ALC2 => 32,
+            OpExt::FRE => 16,
+            OpExt::SLP | OpExt::ASY | OpExt::CMT => 1,
+        }
+    }
+
+    /**
+     * the arguments following the extension code, CMT is also followed
+     * by as many bytes as its argument
+     */
+    pub fn args(&self) -> &'static [Arg] {
+        match self {
+            OpExt::ASY => &[],
+            OpExt::APG | OpExt::FPG | OpExt::FRE => &[Arg::Addr],
+            OpExt::ALC1 => &[Arg::Addr, Arg::Val(8)],
+            OpExt::ALC2 => &[Arg::Addr, Arg::Addr],
+            OpExt::SLP | OpExt::CMT => &[Arg::Val(8)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operands_round_trip() {
+        let operands = [
+            Operand::Direct(0),
+            Operand::Direct(1000),
+            Operand::Direct((1 << MODE_SHIFT) - 1),
+            Operand::Indirect(1000, 0),
+            Operand::Indirect(BASE_MASK, FIELD_MASK),
+            Operand::Indexed(1000, 16),
+            Operand::Indexed(BASE_MASK, FIELD_MASK),
+        ];
+
+        for &operand in &operands {
+            let raw = operand.encode().unwrap();
+            assert_eq!(Operand::decode(raw), Some(operand), "{}", operand);
+        }
+    }
+
+    #[test]
+    fn out_of_range_operands_are_rejected() {
+        assert_eq!(Operand::Direct(1 << MODE_SHIFT).encode(), None);
+        assert_eq!(Operand::Indirect(BASE_MASK + 1, 0).encode(), None);
+        assert_eq!(Operand::Indirect(0, FIELD_MASK + 1).encode(), None);
+        assert_eq!(Operand::Indexed(BASE_MASK + 1, 0).encode(), None);
+        assert_eq!(Operand::Indexed(0, FIELD_MASK + 1).encode(), None);
+
+        assert_eq!(Operand::decode(3 << MODE_SHIFT), None);
+        assert_eq!(Operand::resolve(usize::MAX, |_| Ok::<_, TrapKind>(0)),
+                   Err(TrapKind::IllegalInstruction));
+    }
+
+    #[test]
+    fn resolve_reads_through_load() {
+        let load = |addr| Ok::<_, TrapKind>(addr * 2);
+        let raw = |operand: Operand| operand.encode().unwrap();
+
+        assert_eq!(Operand::resolve(raw(Operand::Direct(7)), load), Ok(7));
+        assert_eq!(Operand::resolve(raw(Operand::Indirect(100, 5)), load), Ok(205));
+        assert_eq!(Operand::resolve(raw(Operand::Indexed(100, 5)), load), Ok(110));
+        assert_eq!(Operand::resolve(raw(Operand::Indexed(100, 5)),
+                                    |_| Err(TrapKind::NullPointer)),
+                   Err(TrapKind::NullPointer));
+    }
+}
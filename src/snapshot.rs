@@ -0,0 +1,156 @@
+/*
+ * Snapshots of a vm, written by Vm::snapshot and read back by
+ * Vm::restore. Everything is little endian, usizes are written as 8
+ * bytes and options as a 0 or 1 byte followed by the value if it's 1:
+ *
+ * "MVMS" version:u32
+ * pc state fuel:option<u64>
+ * heap: free lists, small block pages, blocks
+ * memory: config sp code_end guard fast pages regions
+ *
+ * Host functions, devices, the console and the tracer belong to the
+ * embedder so they are not part of the snapshot, restoring keeps the
+ * ones already set up on the vm.
+ */
+
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Read, Write};
+
+pub const MAGIC: &[u8; 4] = b"MVMS";
+pub const VERSION: u32 = 1;
+
+/**
+ * an error for a snapshot which can't be restored
+ */
+pub fn invalid(msg: String) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, msg);
+}
+
+pub(crate) struct Encoder<'a> {
+    out: &'a mut dyn Write,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(out: &'a mut dyn Write) -> Encoder<'a> {
+        return Encoder{ out };
+    }
+
+    pub fn header(&mut self) -> io::Result<()> {
+        self.out.write_all(MAGIC)?;
+        return self.out.write_all(&VERSION.to_le_bytes());
+    }
+
+    pub fn u8(&mut self, val: u8) -> io::Result<()> {
+        return self.out.write_all(&[val]);
+    }
+
+    pub fn u64(&mut self, val: u64) -> io::Result<()> {
+        return self.out.write_all(&val.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, val: usize) -> io::Result<()> {
+        return self.u64(val as u64);
+    }
+
+    pub fn option<T, F>(&mut self, val: Option<T>, f: F) -> io::Result<()>
+        where F: FnOnce(&mut Self, T) -> io::Result<()> {
+
+        match val {
+            Some(x) => {
+                self.u8(1)?;
+                return f(self, x);
+            },
+            None => return self.u8(0),
+        }
+    }
+
+    /**
+     * bytes with their length in front
+     */
+    pub fn bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.usize(bytes.len())?;
+        return self.out.write_all(bytes);
+    }
+}
+
+pub(crate) struct Decoder<'a> {
+    input: &'a mut dyn Read,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(input: &'a mut dyn Read) -> Decoder<'a> {
+        return Decoder{ input };
+    }
+
+    pub fn header(&mut self) -> io::Result<()> {
+        let mut magic = [0; 4];
+        self.input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a snapshot".to_string()));
+        }
+
+        let mut version = [0; 4];
+        self.input.read_exact(&mut version)?;
+        match u32::from_le_bytes(version) {
+            VERSION => return Ok(()),
+            v => return Err(invalid(format!("unsupported snapshot version {}", v))),
+        }
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0; 1];
+        self.input.read_exact(&mut buf)?;
+        return Ok(buf[0]);
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        self.input.read_exact(&mut buf)?;
+        return Ok(u64::from_le_bytes(buf));
+    }
+
+    pub fn usize(&mut self) -> io::Result<usize> {
+        let val = self.u64()?;
+        return usize::try_from(val)
+            .map_err(|_| invalid(format!("{} too big for this machine", val)));
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        match self.u8()? {
+            0 => return Ok(false),
+            1 => return Ok(true),
+            b => return Err(invalid(format!("bad flag byte {}", b))),
+        }
+    }
+
+    pub fn option<T, F>(&mut self, f: F) -> io::Result<Option<T>>
+        where F: FnOnce(&mut Self) -> io::Result<T> {
+
+        if self.bool()? {
+            return Ok(Some(f(self)?));
+        }
+
+        return Ok(None);
+    }
+
+    /**
+     * bytes written by Encoder::bytes, which must be len long
+     */
+    pub fn bytes(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let n = self.usize()?;
+        if n != len {
+            return Err(invalid(format!("expected {} bytes, got {}", len, n)));
+        }
+
+        // read rather than allocate up front, so a corrupt length can't
+        // ask for more memory than the file has
+        let mut ret = vec![];
+        (&mut *self.input).take(n as u64).read_to_end(&mut ret)?;
+        if ret.len() != n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        return Ok(ret);
+    }
+}
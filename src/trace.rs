@@ -0,0 +1,107 @@
+use std::convert::TryFrom;
+use std::io::Write;
+
+use crate::op_code::Op;
+use crate::trap::Trap;
+
+/**
+ * Hooks called by the vm as it runs a program. Every hook does nothing
+ * by default, so a tracer only implements what it is interested in.
+ *
+ * Memory hooks are only called for values the program operates on,
+ * reading op codes and their arguments is covered by on_fetch.
+ */
+pub trait Tracer {
+    fn on_fetch(&mut self, _pc: usize, _op: u8) {}
+    fn on_memory_read(&mut self, _addr: usize, _bytes: &[u8]) {}
+    fn on_memory_write(&mut self, _addr: usize, _bytes: &[u8]) {}
+    fn on_trap(&mut self, _trap: &Trap) {}
+}
+
+/**
+ * op code name for traces, op codes that don't decode are shown as hex
+ */
+fn op_name(op: u8) -> String {
+    match Op::try_from(op) {
+        Ok(code) => code.to_string(),
+        Err(_) => format!("{:#04x}", op),
+    }
+}
+
+/**
+ * Human readable trace, one event per line.
+ */
+pub struct TextTracer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(out: W) -> TextTracer<W> {
+        return TextTracer{ out };
+    }
+}
+
+impl<W: Write> Tracer for TextTracer<W> {
+    fn on_fetch(&mut self, pc: usize, op: u8) {
+        let _ = writeln!(self.out, "{:>8} {}", pc, op_name(op));
+    }
+
+    fn on_memory_read(&mut self, addr: usize, bytes: &[u8]) {
+        let _ = writeln!(self.out, "{:>8}   read  {} {:02x?}", "", addr, bytes);
+    }
+
+    fn on_memory_write(&mut self, addr: usize, bytes: &[u8]) {
+        let _ = writeln!(self.out, "{:>8}   write {} {:02x?}", "", addr, bytes);
+    }
+
+    fn on_trap(&mut self, trap: &Trap) {
+        let _ = writeln!(self.out, "trap: {}", trap);
+    }
+}
+
+/**
+ * Machine readable trace, one JSON object per line.
+ */
+pub struct JsonTracer<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(out: W) -> JsonTracer<W> {
+        return JsonTracer{ out };
+    }
+}
+
+fn json_opt(x: Option<usize>) -> String {
+    match x {
+        Some(x) => x.to_string(),
+        None => "null".to_string(),
+    }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+    fn on_fetch(&mut self, pc: usize, op: u8) {
+        let _ = writeln!(self.out,
+            r#"{{"event":"fetch","pc":{},"op":{},"name":"{}"}}"#,
+            pc, op, op_name(op));
+    }
+
+    fn on_memory_read(&mut self, addr: usize, bytes: &[u8]) {
+        let _ = writeln!(self.out,
+            r#"{{"event":"read","addr":{},"bytes":{:?}}}"#,
+            addr, bytes);
+    }
+
+    fn on_memory_write(&mut self, addr: usize, bytes: &[u8]) {
+        let _ = writeln!(self.out,
+            r#"{{"event":"write","addr":{},"bytes":{:?}}}"#,
+            addr, bytes);
+    }
+
+    fn on_trap(&mut self, trap: &Trap) {
+        let _ = writeln!(self.out,
+            r#"{{"event":"trap","kind":"{:?}","pc":{},"op":{},"addr":{}}}"#,
+            trap.kind, trap.pc, json_opt(trap.op.map(usize::from)),
+            json_opt(trap.addr));
+    }
+}
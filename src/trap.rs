@@ -0,0 +1,144 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+use crate::op_code::Op;
+
+/**
+ * Why the vm stopped running a program.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TrapKind {
+    NullPointer = 0,
+    PageNotExist = 1,
+    OutOfBounds = 2, // access runs off the end of fast memory or a page
+    IllegalInstruction = 3, // byte at pc is not an op code
+    StackOverflow = 4, // stack would grow into the program
+    StackUnderflow = 5, // pop past the base of the stack
+    DivideByZero = 6,
+    Unimplemented = 7,
+    OutOfFuel = 8, // resumable by adding fuel
+    HostCall = 9, // no host function registered with the number
+    HostError = 10, // raised by a host function
+    Protection = 11, // access not allowed by the memory's permissions
+    OutOfMemory = 12, // no more pages can be allocated
+    DoubleFree = 13, // heap block freed twice
+    InvalidFree = 14, // free of an address the heap never allocated
+}
+
+impl TrapKind {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            TrapKind::NullPointer => "null pointer deref",
+            TrapKind::PageNotExist => "page does not exist",
+            TrapKind::OutOfBounds => "access out of bounds",
+            TrapKind::IllegalInstruction => "illegal instruction",
+            TrapKind::StackOverflow => "stack overflow",
+            TrapKind::StackUnderflow => "stack underflow",
+            TrapKind::DivideByZero => "divide by zero",
+            TrapKind::Unimplemented => "op code not implemented",
+            TrapKind::OutOfFuel => "out of fuel",
+            TrapKind::HostCall => "no such host call",
+            TrapKind::HostError => "host call failed",
+            TrapKind::Protection => "memory protection violation",
+            TrapKind::OutOfMemory => "out of memory",
+            TrapKind::DoubleFree => "double free",
+            TrapKind::InvalidFree => "free of unallocated address",
+        }
+    }
+}
+
+/**
+ * decode a kind written as `kind as u8`, for snapshots. The numbers are
+ * part of the snapshot format so they must not change.
+ */
+impl TryFrom<u8> for TrapKind {
+    type Error = String;
+
+    fn try_from(i: u8) -> Result<TrapKind, String> {
+        match i {
+            0 => return Ok(TrapKind::NullPointer),
+            1 => return Ok(TrapKind::PageNotExist),
+            2 => return Ok(TrapKind::OutOfBounds),
+            3 => return Ok(TrapKind::IllegalInstruction),
+            4 => return Ok(TrapKind::StackOverflow),
+            5 => return Ok(TrapKind::StackUnderflow),
+            6 => return Ok(TrapKind::DivideByZero),
+            7 => return Ok(TrapKind::Unimplemented),
+            8 => return Ok(TrapKind::OutOfFuel),
+            9 => return Ok(TrapKind::HostCall),
+            10 => return Ok(TrapKind::HostError),
+            11 => return Ok(TrapKind::Protection),
+            12 => return Ok(TrapKind::OutOfMemory),
+            13 => return Ok(TrapKind::DoubleFree),
+            14 => return Ok(TrapKind::InvalidFree),
+            _ => return Err(format!("{} not a trap kind", i)),
+        }
+    }
+}
+
+/**
+ * A fault raised by memory or an instruction, it becomes a Trap once the
+ * vm adds where it happened.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub kind: TrapKind,
+    pub addr: Option<usize>,
+}
+
+impl Fault {
+    pub fn new(kind: TrapKind, addr: usize) -> Fault {
+        return Fault{ kind, addr: Some(addr) };
+    }
+
+    pub fn at(self, pc: usize, op: Option<u8>) -> Trap {
+        return Trap{
+            kind: self.kind,
+            pc,
+            op,
+            addr: self.addr,
+        };
+    }
+}
+
+impl From<TrapKind> for Fault {
+    fn from(kind: TrapKind) -> Fault {
+        return Fault{ kind, addr: None };
+    }
+}
+
+/**
+ * A trapped program, with the pc and op code of the faulting instruction
+ * and the address it was accessing, if any. The op code is None when the
+ * op code itself could not be fetched.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+    pub kind: TrapKind,
+    pub pc: usize,
+    pub op: Option<u8>,
+    pub addr: Option<usize>,
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at pc {}", self.kind.to_str(), self.pc)?;
+
+        if let Some(op) = self.op {
+            match Op::try_from(op) {
+                Ok(code) => write!(f, " ({})", code)?,
+                Err(_) => write!(f, " (op code {:#04x})", op)?,
+            }
+        }
+
+        if let Some(addr) = self.addr {
+            write!(f, " accessing {}", addr)?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl Error for Trap {}
@@ -0,0 +1,1077 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::io;
+use std::io::{BufRead, Read, Write};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use crate::alu;
+use crate::alu::{FloatOp, IntOp};
+use crate::op_code::{Cond, Op, OpExt, Operand, BLOCK_COST, BLOCK_SIZE, SLEEP_COST};
+use crate::memory::*;
+use crate::console;
+use crate::console::Console;
+use crate::device::Device;
+use crate::heap::Heap;
+use crate::host::{Host, HostFn};
+use crate::snapshot::{invalid, Decoder, Encoder};
+use crate::trace::Tracer;
+use crate::trap::{Fault, Trap, TrapKind};
+
+/**
+ * The exit code a program leaves at PROG_OFFSET
+ */
+pub type ExitStatus = u8;
+
+/**
+ * Whether a program can keep running
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    Exited(ExitStatus),
+    Trapped(Trap),
+}
+
+/**
+ * The virtual machine, a program loaded into memory and the registers
+ * needed to run it.
+ */
+pub struct Vm {
+    memory: Memory,
+    heap: Heap,
+    state: State,
+    tracer: Option<Box<dyn Tracer>>,
+    fuel: Option<u64>, // None is unlimited
+    host: Host,
+    console: Rc<RefCell<Console>>, // shared with the console host calls
+
+    // registers
+    pc: usize,
+}
+
+impl Vm {
+    /**
+     * a vm running code with the default memory layout, panics if the
+     * code does not fit in fast memory
+     */
+    pub fn new(code: &[u8]) -> Vm {
+        match Vm::with_config(code, MemoryConfig::default()) {
+            Ok(vm) => return vm,
+            Err(msg) => panic!("{}", msg),
+        }
+    }
+
+    pub fn with_config(code: &[u8], config: MemoryConfig) -> Result<Vm, String> {
+        let mut ret = Vm{
+            memory: Memory::new(code, config)?,
+            heap: Heap::new(),
+            state: State::Running,
+            tracer: None,
+            fuel: None,
+            host: Host::new(),
+            console: Rc::new(RefCell::new(Console::stdio())),
+            pc: CODE_OFFSET,
+        };
+
+        console::register(&mut ret.host, &ret.console);
+
+        return Ok(ret);
+    }
+
+    /**
+     * trace execution of the program, replacing any previous tracer
+     */
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer>> {
+        return self.tracer.take();
+    }
+
+    /**
+     * limit how much fuel the program can use, each instruction uses
+     * fuel according to its cost. A program that runs out traps with
+     * OutOfFuel and can be resumed by adding more fuel.
+     */
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+        self.refuel();
+    }
+
+    /**
+     * add to the fuel left, a vm without a limit stays unlimited
+     */
+    pub fn add_fuel(&mut self, fuel: u64) {
+        self.fuel = self.fuel.map(|left| left.saturating_add(fuel));
+        self.refuel();
+    }
+
+    /**
+     * remove the fuel limit
+     */
+    pub fn unlimited_fuel(&mut self) {
+        self.fuel = None;
+        self.refuel();
+    }
+
+    /**
+     * fuel left, None if there is no limit
+     */
+    pub fn fuel(&self) -> Option<u64> {
+        return self.fuel;
+    }
+
+    fn refuel(&mut self) {
+        if let State::Trapped(trap) = self.state {
+            if trap.kind == TrapKind::OutOfFuel {
+                self.state = State::Running;
+            }
+        }
+    }
+
+    /**
+     * fuel needed to execute the instruction at pc
+     */
+    fn cost(&self, op: u8) -> u64 {
+        match Op::try_from(op) {
+            Ok(Op::EXT) => {
+                let ext = self.memory.get::<u8>(self.pc + 1)
+                    .ok()
+                    .and_then(|ext| OpExt::try_from(ext).ok());
+
+                // sleeping holds up the host, so it costs as long as it is
+                let sleep = match ext {
+                    Some(OpExt::SLP) => self.memory.get::<u64>(self.pc + 2)
+                        .map_or(0, |ms| ms.saturating_mul(SLEEP_COST)),
+                    _ => 0,
+                };
+
+                return (Op::EXT.cost() + ext.map_or(0, |ext| ext.cost()))
+                    .saturating_add(sleep);
+            },
+            Ok(code @ (Op::MCP1 | Op::MCP2 | Op::MST1 | Op::MST2 |
+                       Op::MCM1 | Op::MCM2)) => {
+                let len = self.block_len(code).unwrap_or(0);
+                return code.cost()
+                    .saturating_add(len / BLOCK_SIZE * BLOCK_COST);
+            },
+            Ok(code) => return code.cost(),
+            // illegal instructions trap when they're executed
+            Err(_) => return 0,
+        }
+    }
+
+    /**
+     * length argument of the block memory instruction at pc, without
+     * tracing the read
+     */
+    fn block_len(&self, code: Op) -> Option<u64> {
+        // op code + dst + (src | byte)
+        let at = match code {
+            Op::MST1 | Op::MST2 => self.pc + 1 + 8 + 1,
+            _ => self.pc + 1 + 8 + 8,
+        };
+
+        match code {
+            Op::MCP1 | Op::MST1 | Op::MCM1 => return self.memory.get(at).ok(),
+            _ => {
+                let raw: usize = self.memory.get(at).ok()?;
+                let ptr = self.memory.resolve(raw).ok()?;
+                return self.memory.get(ptr).ok();
+            },
+        }
+    }
+
+    /**
+     * register a host function the program can call with SYS n
+     */
+    pub fn register_host(&mut self, n: u8, name: &str, f: HostFn) {
+        self.host.register(n, name, f);
+    }
+
+    /**
+     * map a device into memory, see Memory::map_device
+     */
+    pub fn map_device(&mut self, start: usize, len: usize,
+                      device: Box<dyn Device>) -> Result<(), String> {
+        return self.memory.map_device(start, len, device);
+    }
+
+    /**
+     * where the console host calls read from, stdin by default
+     */
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.console.borrow_mut().input = input;
+    }
+
+    /**
+     * where the console host calls write to, stdout by default
+     */
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.console.borrow_mut().output = output;
+    }
+
+    pub fn host(&self) -> &Host {
+        return &self.host;
+    }
+
+    pub fn host_mut(&mut self) -> &mut Host {
+        return &mut self.host;
+    }
+
+    pub fn memory(&self) -> &Memory {
+        return &self.memory;
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        return &mut self.memory;
+    }
+
+    pub fn read<T: Scalar>(&self, addr: usize) -> Result<T, Fault> {
+        return self.memory.get(addr);
+    }
+
+    pub fn write<T: Scalar>(&mut self, addr: usize, val: T) -> Result<(), Fault> {
+        return self.memory.set(addr, val);
+    }
+
+    /**
+     * address of the next instruction, or of the faulting instruction if
+     * the program trapped
+     */
+    pub fn pc(&self) -> usize {
+        return self.pc;
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    pub fn sp(&self) -> usize {
+        return self.memory.sp();
+    }
+
+    pub fn state(&self) -> State {
+        return self.state;
+    }
+
+    /**
+     * Execute a single instruction, if the program is still running.
+     */
+    pub fn step(&mut self) -> State {
+        if self.state != State::Running {
+            return self.state;
+        }
+
+        let pc = self.pc;
+
+        let op:u8 = match self.memory.fetch(pc) {
+            Ok(op) => op,
+            Err(f) => return self.trap(f.at(pc, None)),
+        };
+
+        if let Some(fuel) = self.fuel {
+            let cost = self.cost(op);
+            if cost > fuel {
+                let fault = Fault::from(TrapKind::OutOfFuel);
+                return self.trap(fault.at(pc, Some(op)));
+            }
+
+            self.fuel = Some(fuel - cost);
+        }
+
+        self.pc += 1;
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.on_fetch(pc, op);
+        }
+
+        self.state = match self.exec(op) {
+            Ok(None) => State::Running,
+            Ok(Some(code)) => State::Exited(code),
+            Err(f) => {
+                self.pc = pc;
+                return self.trap(f.at(pc, Some(op)));
+            },
+        };
+
+        return self.state;
+    }
+
+    fn trap(&mut self, trap: Trap) -> State {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.on_trap(&trap);
+        }
+
+        self.state = State::Trapped(trap);
+        return self.state;
+    }
+
+    /**
+     * Execute at most n_steps instructions, stopping early if the program
+     * exits or traps.
+     */
+    pub fn run_until(&mut self, n_steps: usize) -> State {
+        for _ in 0..n_steps {
+            if self.step() != State::Running {
+                break;
+            }
+        }
+
+        return self.state;
+    }
+
+    /**
+     * Run the program until it exits, returning the exit code, or until
+     * it traps.
+     */
+    pub fn run(&mut self) -> Result<ExitStatus, Trap> {
+        loop {
+            match self.step() {
+                State::Running => {},
+                State::Exited(code) => return Ok(code),
+                State::Trapped(trap) => return Err(trap),
+            }
+        }
+    }
+
+    /**
+     * write the program's state to out, so it can be picked up again with
+     * restore. See snapshot for what is kept.
+     */
+    pub fn snapshot<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut enc = Encoder::new(&mut out);
+        enc.header()?;
+
+        enc.usize(self.pc)?;
+        match self.state {
+            State::Running => enc.u8(0)?,
+            State::Exited(code) => {
+                enc.u8(1)?;
+                enc.u8(code)?;
+            },
+            State::Trapped(trap) => {
+                enc.u8(2)?;
+                enc.u8(trap.kind as u8)?;
+                enc.usize(trap.pc)?;
+                enc.option(trap.op, Encoder::u8)?;
+                enc.option(trap.addr, Encoder::usize)?;
+            },
+        }
+        enc.option(self.fuel, Encoder::u64)?;
+
+        self.heap.save(&mut enc)?;
+        self.memory.save(&mut enc)?;
+
+        return out.flush();
+    }
+
+    /**
+     * replace the program's state with a snapshot, keeping the host
+     * functions, devices, console and tracer already set up. Nothing
+     * changes if the snapshot is bad.
+     */
+    pub fn restore<R: Read>(&mut self, mut input: R) -> io::Result<()> {
+        let mut dec = Decoder::new(&mut input);
+        dec.header()?;
+
+        let pc = dec.usize()?;
+        let state = match dec.u8()? {
+            0 => State::Running,
+            1 => State::Exited(dec.u8()?),
+            2 => State::Trapped(Trap{
+                kind: TrapKind::try_from(dec.u8()?).map_err(invalid)?,
+                pc: dec.usize()?,
+                op: dec.option(Decoder::u8)?,
+                addr: dec.option(Decoder::usize)?,
+            }),
+            b => return Err(invalid(format!("bad vm state {}", b))),
+        };
+        let fuel = dec.option(Decoder::u64)?;
+
+        let mut heap = Heap::new();
+        heap.restore(&mut dec)?;
+        self.memory.restore(&mut dec)?;
+
+        self.heap = heap;
+        self.pc = pc;
+        self.state = state;
+        self.fuel = fuel;
+
+        return Ok(());
+    }
+
+    /**
+     * the effective address of the address operand at the given address in
+     * the instruction stream
+     */
+    fn addr_at(&mut self, at: usize) -> Result<usize, Fault> {
+        let raw: usize = self.memory.get(at)?;
+        return Operand::resolve(raw, |addr| self.load(addr));
+    }
+
+    fn addrs_at(&mut self, at: usize) -> Result<[usize; 2], Fault> {
+        return Ok([self.addr_at(at)?, self.addr_at(at + 8)?]);
+    }
+
+    /**
+     * load a value the program is operating on, as opposed to the
+     * instruction stream
+     */
+    fn load<T: Scalar>(&mut self, addr: usize) -> Result<T, Fault> {
+        let val = self.memory.get(addr)?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.on_memory_read(addr, scalar_bytes(&val));
+        }
+        return Ok(val);
+    }
+
+    fn store<T: Scalar>(&mut self, addr: usize, val: T) -> Result<(), Fault> {
+        self.memory.set(addr, val)?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.on_memory_write(addr, scalar_bytes(&val));
+        }
+        return Ok(());
+    }
+
+    fn push<T: Scalar>(&mut self, val: T) -> Result<(), Fault> {
+        self.memory.push(val)?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.on_memory_write(self.memory.sp(), scalar_bytes(&val));
+        }
+        return Ok(());
+    }
+
+    fn pop<T: Scalar>(&mut self) -> Result<T, Fault> {
+        let addr = self.memory.sp();
+        let val = self.memory.pop()?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.on_memory_read(addr, scalar_bytes(&val));
+        }
+        return Ok(val);
+    }
+
+    /*
+     * block memory ops read each range once, since it may be a device
+     */
+
+    fn copy(&mut self, dst: usize, src: usize, len: usize) -> Result<(), Fault> {
+        self.memory.check_range(dst, len)?;
+        self.memory.check(dst, len, Perm::WRITE)?;
+        let bytes = self.memory.read_range(src, len)?;
+        self.memory.write_range(dst, &bytes)?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.on_memory_read(src, &bytes);
+            tracer.on_memory_write(dst, &bytes);
+        }
+        return Ok(());
+    }
+
+    fn fill(&mut self, dst: usize, val: u8, len: usize) -> Result<(), Fault> {
+        self.memory.fill(dst, val, len)?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.on_memory_write(dst, &vec![val; len]);
+        }
+        return Ok(());
+    }
+
+    /**
+     * compare the bytes at a and b, setting flags like CMF
+     */
+    fn compare(&mut self, a: usize, b: usize, len: usize) -> Result<(), Fault> {
+        let av = self.memory.read_range(a, len)?;
+        let bv = self.memory.read_range(b, len)?;
+        let flags = match av.cmp(&bv) {
+            Ordering::Equal => alu::ZERO,
+            Ordering::Less => alu::CARRY | alu::NEGATIVE,
+            Ordering::Greater => 0,
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.on_memory_read(a, &av);
+            tracer.on_memory_read(b, &bv);
+        }
+        return self.store(FLAGS_ADDR, flags);
+    }
+
+    /**
+     * Execute a single op code, pc should already point past the op code.
+     * Returns the exit code if the op code exits the program.
+     */
+    fn exec(&mut self, op: u8) -> Result<Option<ExitStatus>, Fault> {
+        let code = match Op::try_from(op) {
+            Ok(code) => code,
+            Err(_) => return Err(TrapKind::IllegalInstruction.into()),
+        };
+
+        match code {
+            Op::NOP => {},// nop
+            Op::XIT => {
+                return Ok(Some(self.load::<u8>(PROG_OFFSET)?));
+            },
+
+            //
+            // floating point conversions, arithmetic is decoded by family
+            //
+
+            Op::ITF1 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: i64 = self.load(src)?;
+                self.store(dst, srcv as f32)?;
+            },
+            Op::ITF2 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: i64 = self.load(src)?;
+                self.store(dst, srcv as f64)?;
+            },
+            // out of range values saturate and NaN is 0
+            Op::FTI1 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: f32 = self.load(src)?;
+                self.store(dst, srcv as i64)?;
+            },
+            Op::FTI2 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: f64 = self.load(src)?;
+                self.store(dst, srcv as i64)?;
+            },
+            Op::FRI1 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: f32 = self.load(src)?;
+                self.store(dst, srcv.round() as i64)?;
+            },
+            Op::FRI2 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: f64 = self.load(src)?;
+                self.store(dst, srcv.round() as i64)?;
+            },
+            Op::FEX => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: f32 = self.load(src)?;
+                self.store(dst, srcv as f64)?;
+            },
+            Op::FNR => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: f64 = self.load(src)?;
+                self.store(dst, srcv as f32)?;
+            },
+
+            //
+            // memory operations
+            //
+
+            Op::CPY1 => {
+                let dst = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let srcv: u8 = self.memory.get(self.pc)?;
+                self.pc += 1;
+                self.store(dst, srcv)?;
+            },
+            Op::CPY2 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: u8 = self.load(src)?;
+                self.store(dst, srcv)?;
+            },
+            Op::CPY3 => {
+                let dst = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let srcv: u64 = self.memory.get(self.pc)?;
+                self.pc += 8;
+                self.store(dst, srcv)?;
+            },
+            Op::CPY4 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: u64 = self.load(src)?;
+                self.store(dst, srcv)?;
+            },
+            Op::CPY5 => {
+                let dst = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let srcv: u16 = self.memory.get(self.pc)?;
+                self.pc += 2;
+                self.store(dst, srcv)?;
+            },
+            Op::CPY6 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: u16 = self.load(src)?;
+                self.store(dst, srcv)?;
+            },
+            Op::CPY7 => {
+                let dst = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let srcv: u32 = self.memory.get(self.pc)?;
+                self.pc += 4;
+                self.store(dst, srcv)?;
+            },
+            Op::CPY8 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let srcv: u32 = self.load(src)?;
+                self.store(dst, srcv)?;
+            },
+
+            Op::MCP1 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let len: usize = self.memory.get(self.pc)?;
+                self.pc += 8;
+                self.copy(dst, src, len)?;
+            },
+            Op::MCP2 => {
+                let [dst, src] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let len_ptr = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let len: usize = self.load(len_ptr)?;
+                self.copy(dst, src, len)?;
+            },
+            Op::MST1 => {
+                let dst = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let val: u8 = self.memory.get(self.pc)?;
+                self.pc += 1;
+                let len: usize = self.memory.get(self.pc)?;
+                self.pc += 8;
+                self.fill(dst, val, len)?;
+            },
+            Op::MST2 => {
+                let dst = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let val: u8 = self.memory.get(self.pc)?;
+                self.pc += 1;
+                let len_ptr = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let len: usize = self.load(len_ptr)?;
+                self.fill(dst, val, len)?;
+            },
+            Op::MCM1 => {
+                let [a, b] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let len: usize = self.memory.get(self.pc)?;
+                self.pc += 8;
+                self.compare(a, b, len)?;
+            },
+            Op::MCM2 => {
+                let [a, b] = self.addrs_at(self.pc)?;
+                self.pc += 16;
+                let len_ptr = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let len: usize = self.load(len_ptr)?;
+                self.compare(a, b, len)?;
+            },
+
+            //
+            // control flow
+            //
+
+            Op::JMP1 => {
+                self.pc = self.memory.get(self.pc)?;
+            },
+            Op::JMP2 => {
+                let ptr = self.addr_at(self.pc)?;
+                self.pc = self.load(ptr)?;
+            },
+            Op::JIT => {
+                let dst: usize = self.memory.get(self.pc)?;
+                let cond = self.addr_at(self.pc + 8)?;
+                self.pc += 16;
+                let condv: u8 = self.load(cond)?;
+                if condv != 0 {
+                    self.pc = dst;
+                }
+            },
+            Op::JFS => {
+                let dst: usize = self.memory.get(self.pc)?;
+                self.pc += 8;
+                let mask: u8 = self.memory.get(self.pc)?;
+                self.pc += 1;
+                let flags: u8 = self.load(FLAGS_ADDR)?;
+                if flags & mask != 0 {
+                    self.pc = dst;
+                }
+            },
+            Op::JFC => {
+                let dst: usize = self.memory.get(self.pc)?;
+                self.pc += 8;
+                let mask: u8 = self.memory.get(self.pc)?;
+                self.pc += 1;
+                let flags: u8 = self.load(FLAGS_ADDR)?;
+                if flags & mask == 0 {
+                    self.pc = dst;
+                }
+            },
+            Op::CAL => {
+                let dst: usize = self.memory.get(self.pc)?;
+                self.pc += 8;
+                self.push(self.pc)?;
+                self.pc = dst;
+            },
+            Op::RET => {
+                self.pc = self.pop()?;
+            },
+
+            //
+            // stack
+            //
+
+            Op::PSH1 => {
+                let srcv: u8 = self.memory.get(self.pc)?;
+                self.pc += 1;
+                self.push(srcv)?;
+            },
+            Op::PSH2 => {
+                let src = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let srcv: u8 = self.load(src)?;
+                self.push(srcv)?;
+            },
+            Op::PSH3 => {
+                let srcv: u64 = self.memory.get(self.pc)?;
+                self.pc += 8;
+                self.push(srcv)?;
+            },
+            Op::PSH4 => {
+                let src = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let srcv: u64 = self.load(src)?;
+                self.push(srcv)?;
+            },
+            Op::PSH5 => {
+                let srcv: u16 = self.memory.get(self.pc)?;
+                self.pc += 2;
+                self.push(srcv)?;
+            },
+            Op::PSH6 => {
+                let src = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let srcv: u16 = self.load(src)?;
+                self.push(srcv)?;
+            },
+            Op::PSH7 => {
+                let srcv: u32 = self.memory.get(self.pc)?;
+                self.pc += 4;
+                self.push(srcv)?;
+            },
+            Op::PSH8 => {
+                let src = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let srcv: u32 = self.load(src)?;
+                self.push(srcv)?;
+            },
+
+            // odd variants discard the popped value
+            Op::POP1 => {
+                self.pop::<u8>()?;
+            },
+            Op::POP2 => {
+                let dst = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let dstv: u8 = self.pop()?;
+                self.store(dst, dstv)?;
+            },
+            Op::POP3 => {
+                self.pop::<u64>()?;
+            },
+            Op::POP4 => {
+                let dst = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let dstv: u64 = self.pop()?;
+                self.store(dst, dstv)?;
+            },
+            Op::POP5 => {
+                self.pop::<u16>()?;
+            },
+            Op::POP6 => {
+                let dst = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let dstv: u16 = self.pop()?;
+                self.store(dst, dstv)?;
+            },
+            Op::POP7 => {
+                self.pop::<u32>()?;
+            },
+            Op::POP8 => {
+                let dst = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let dstv: u32 = self.pop()?;
+                self.store(dst, dstv)?;
+            },
+
+            Op::SYS => {
+                let n: u8 = self.memory.get(self.pc)?;
+                self.pc += 1;
+                let ret = match self.host.get_mut(n) {
+                    Some(call) => (call.f)(&mut self.memory)?,
+                    None => return Err(TrapKind::HostCall.into()),
+                };
+                self.push(ret)?;
+            },
+
+            Op::EXT => {
+                let ext: u8 = self.memory.get(self.pc)?;
+                self.pc += 1;
+                match OpExt::try_from(ext) {
+                    Ok(ext) => self.exec_ext(ext)?,
+                    Err(_) => return Err(TrapKind::IllegalInstruction.into()),
+                }
+            },
+
+            //
+            // integer and float arithmetic and comparisons, decoded by
+            // family
+            //
+
+            _ => {
+                if let Some((op, width, imm)) = code.int_op() {
+                    self.exec_int(op, width, imm)?;
+                } else if let Some((op, width, imm)) = code.float_op() {
+                    self.exec_float(op, width, imm)?;
+                } else if let Some((width, imm)) = code.int_compare() {
+                    self.exec_compare(width, imm, false)?;
+                } else if let Some((width, imm)) = code.float_compare() {
+                    self.exec_compare(width, imm, true)?;
+                } else {
+                    return Err(TrapKind::Unimplemented.into());
+                }
+            },
+        }
+
+        return Ok(None);
+    }
+
+    /**
+     * load an unsigned integer of width bytes, zero extended
+     */
+    fn load_uint(&mut self, addr: usize, width: usize) -> Result<u64, Fault> {
+        match width {
+            1 => return Ok(self.load::<u8>(addr)? as u64),
+            2 => return Ok(self.load::<u16>(addr)? as u64),
+            4 => return Ok(self.load::<u32>(addr)? as u64),
+            _ => return self.load::<u64>(addr),
+        }
+    }
+
+    /**
+     * store the low width bytes of an integer
+     */
+    fn store_uint(&mut self, addr: usize, width: usize, val: u64)
+        -> Result<(), Fault> {
+
+        match width {
+            1 => return self.store(addr, val as u8),
+            2 => return self.store(addr, val as u16),
+            4 => return self.store(addr, val as u32),
+            _ => return self.store(addr, val),
+        }
+    }
+
+    /**
+     * read a width byte integer operand from the instruction stream
+     */
+    fn fetch_uint(&mut self, width: usize) -> Result<u64, Fault> {
+        let val = match width {
+            1 => self.memory.get::<u8>(self.pc)? as u64,
+            2 => self.memory.get::<u16>(self.pc)? as u64,
+            4 => self.memory.get::<u32>(self.pc)? as u64,
+            _ => self.memory.get::<u64>(self.pc)?,
+        };
+        self.pc += width;
+        return Ok(val);
+    }
+
+    /**
+     * Execute an integer op, the result wraps to the width and the flags
+     * it produces are written to FLAGS_ADDR.
+     */
+    fn exec_int(&mut self, op: IntOp, width: usize, imm: bool)
+        -> Result<(), Fault> {
+
+        let dst = self.addr_at(self.pc)?;
+        self.pc += 8;
+        let dstv = self.load_uint(dst, width)?;
+
+        let (srcv, src) = if imm {
+            (self.fetch_uint(width)?, None)
+        } else {
+            let src = self.addr_at(self.pc)?;
+            self.pc += 8;
+            (self.load_uint(src, width)?, Some(src))
+        };
+
+        let (res, flags) = match alu::eval(op, width, dstv, srcv) {
+            Ok(ret) => ret,
+            Err(kind) => return Err(Fault{kind, addr: src}),
+        };
+
+        self.store_uint(dst, width, res)?;
+        self.store(FLAGS_ADDR, flags)?;
+
+        return Ok(());
+    }
+
+    /**
+     * load an f32 or f64 as an f64
+     */
+    fn load_float(&mut self, addr: usize, width: usize) -> Result<f64, Fault> {
+        match width {
+            4 => return Ok(self.load::<f32>(addr)? as f64),
+            _ => return self.load::<f64>(addr),
+        }
+    }
+
+    /**
+     * read an f32 or f64 operand from the instruction stream
+     */
+    fn fetch_float(&mut self, width: usize) -> Result<f64, Fault> {
+        let val = match width {
+            4 => self.memory.get::<f32>(self.pc)? as f64,
+            _ => self.memory.get::<f64>(self.pc)?,
+        };
+        self.pc += width;
+        return Ok(val);
+    }
+
+    /**
+     * Execute a float op on f32 or f64 operands, CMF writes flags to
+     * FLAGS_ADDR instead of a result.
+     */
+    fn exec_float(&mut self, op: FloatOp, width: usize, imm: bool)
+        -> Result<(), Fault> {
+
+        let dst = self.addr_at(self.pc)?;
+        self.pc += 8;
+        let dstv = self.load_float(dst, width)?;
+
+        let srcv = if imm {
+            self.fetch_float(width)?
+        } else {
+            let src = self.addr_at(self.pc)?;
+            self.pc += 8;
+            self.load_float(src, width)?
+        };
+
+        match alu::eval_float(op, dstv, srcv) {
+            (_, Some(flags)) => self.store(FLAGS_ADDR, flags)?,
+            (res, None) if width == 4 => self.store(dst, res as f32)?,
+            (res, None) => self.store(dst, res)?,
+        }
+
+        return Ok(());
+    }
+
+    /**
+     * Execute a comparison of integers or floats, writing 1 to the byte at
+     * dst if it holds and 0 otherwise.
+     */
+    fn exec_compare(&mut self, width: usize, imm: bool, float: bool)
+        -> Result<(), Fault> {
+
+        let cond: u8 = self.memory.get(self.pc)?;
+        self.pc += 1;
+        let cond = match Cond::try_from(cond) {
+            Ok(cond) => cond,
+            Err(_) => return Err(TrapKind::IllegalInstruction.into()),
+        };
+
+        let [dst, a] = self.addrs_at(self.pc)?;
+        self.pc += 16;
+
+        let res = if float {
+            let av = self.load_float(a, width)?;
+            let bv = if imm {
+                self.fetch_float(width)?
+            } else {
+                let b = self.addr_at(self.pc)?;
+                self.pc += 8;
+                self.load_float(b, width)?
+            };
+            alu::compare_float(cond, av, bv)
+        } else {
+            let av = self.load_uint(a, width)?;
+            let bv = if imm {
+                self.fetch_uint(width)?
+            } else {
+                let b = self.addr_at(self.pc)?;
+                self.pc += 8;
+                self.load_uint(b, width)?
+            };
+            alu::compare(cond, width, av, bv)
+        };
+
+        self.store(dst, res as u8)?;
+
+        return Ok(());
+    }
+
+    /**
+     * Execute an extension code, pc should already point past it.
+     */
+    fn exec_ext(&mut self, ext: OpExt) -> Result<(), Fault> {
+        match ext {
+            OpExt::SLP => {
+                let ms: u64 = self.memory.get(self.pc)?;
+                self.pc += 8;
+                thread::sleep(Duration::from_millis(ms));
+            },
+            OpExt::APG => {
+                let dst = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let page = self.memory.alloc_page()?;
+                self.store(dst, page)?;
+            },
+            OpExt::FPG => {
+                let src = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let page: usize = self.load(src)?;
+                if self.heap.owns(page, self.memory.config().page_size) {
+                    return Err(Fault::new(TrapKind::InvalidFree, page));
+                }
+                self.memory.free_page(page)?;
+            },
+            OpExt::CMT => {
+                let len: usize = self.memory.get(self.pc)?;
+                self.pc = match len.checked_add(self.pc + 8) {
+                    Some(pc) => pc,
+                    None => return Err(Fault::new(TrapKind::OutOfBounds, self.pc)),
+                };
+            },
+            OpExt::ALC1 | OpExt::ALC2 => {
+                let dst = self.addr_at(self.pc)?;
+                let len: usize = match ext {
+                    OpExt::ALC1 => self.memory.get(self.pc + 8)?,
+                    _ => {
+                        let src = self.addr_at(self.pc + 8)?;
+                        self.load(src)?
+                    },
+                };
+                self.pc += 16;
+                let addr = self.heap.alloc(&mut self.memory, len)?;
+                self.store(dst, addr)?;
+            },
+            OpExt::FRE => {
+                let src = self.addr_at(self.pc)?;
+                self.pc += 8;
+                let addr: usize = self.load(src)?;
+                self.heap.free(&mut self.memory, addr)?;
+            },
+            OpExt::ASY => return Err(TrapKind::Unimplemented.into()),
+        }
+
+        return Ok(());
+    }
+}
+
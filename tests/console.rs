@@ -0,0 +1,87 @@
+#![allow(clippy::needless_return)]
+
+use std::cell::RefCell;
+use std::io;
+use std::io::Read;
+use std::rc::Rc;
+
+use mvm::console::Capture;
+use mvm::{assemble, TrapKind, Vm};
+
+/**
+ * a vm running src with input and its output captured
+ */
+fn vm(src: &str, input: &'static str) -> (Vm, Capture) {
+    let mut vm = Vm::new(&assemble(src).unwrap());
+    let out = Capture::new();
+    vm.set_input(Box::new(io::Cursor::new(input)));
+    vm.set_output(Box::new(out.clone()));
+    return (vm, out);
+}
+
+#[test]
+fn output_is_captured() {
+    let src = "
+.start
+    pshw 104
+    sys 0
+    pshw 105
+    sys 0
+    xit
+";
+    let (mut vm, out) = vm(src, "");
+    assert_eq!(vm.run(), Ok(0));
+    assert_eq!(out.contents(), b"hi");
+}
+
+#[test]
+fn lines_cross_pages() {
+    // read a line into the end of one page and the start of the next,
+    // then write it back out
+    let src = "
+.start
+    apg &1000
+    apg &1008
+    addw &1000 4094
+    pshw &1000
+    pshw 10
+    sys 3
+    popb ._zero
+    pshw &1000
+    pshw 6
+    sys 1
+    xit
+";
+    let (mut vm, out) = vm(src, "hello\nworld\n");
+    assert_eq!(vm.run(), Ok(6));
+    assert_eq!(out.contents(), b"hello\n");
+}
+
+/**
+ * input whose position can be checked after handing it to the vm
+ */
+#[derive(Clone)]
+struct Shared(Rc<RefCell<io::Cursor<&'static str>>>);
+
+impl Read for Shared {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return self.0.borrow_mut().read(buf);
+    }
+}
+
+#[test]
+fn input_is_kept_when_the_line_cant_be_stored() {
+    let src = "
+.start
+    pshw 0
+    pshw 10
+    sys 3
+    xit
+";
+    let (mut vm, _) = vm(src, "");
+    let input = Shared(Rc::new(RefCell::new(io::Cursor::new("hello\n"))));
+    vm.set_input(Box::new(io::BufReader::new(input.clone())));
+
+    assert_eq!(vm.run().unwrap_err().kind, TrapKind::NullPointer);
+    assert_eq!(input.0.borrow().position(), 0);
+}
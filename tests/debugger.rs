@@ -0,0 +1,34 @@
+#![allow(clippy::needless_return)]
+
+use mvm::debugger::Debugger;
+use mvm::op_code::{Op, OpExt};
+use mvm::Vm;
+
+fn run(code: &[u8], cmd: &str) -> String {
+    let mut debugger = Debugger::new(Vm::new(code), Default::default());
+    let mut out = vec![];
+    assert!(debugger.command(cmd, &mut out).unwrap());
+    return String::from_utf8(out).unwrap();
+}
+
+#[test]
+fn dump_past_end_of_memory_is_an_error() {
+    let out = run(&[Op::XIT as u8], "x 0xffffffffffffffff 64");
+    assert!(out.contains("runs past the end of memory"), "{}", out);
+
+    let out = run(&[Op::XIT as u8], "x 64 100000000000");
+    assert!(out.contains("can't dump more than 65536 bytes"), "{}", out);
+    assert_eq!(out.lines().count(), 1, "{}", out);
+
+    let out = run(&[Op::XIT as u8], "x 0xfffffffffffffff0 15");
+    assert!(out.contains("??"), "{}", out);
+}
+
+#[test]
+fn list_stops_at_huge_comment() {
+    let mut code = vec![Op::EXT as u8, OpExt::CMT as u8];
+    code.extend_from_slice(&u64::MAX.to_le_bytes());
+
+    let out = run(&code, "list");
+    assert!(out.contains("??"), "{}", out);
+}
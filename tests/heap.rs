@@ -0,0 +1,78 @@
+use mvm::heap::Heap;
+use mvm::{assemble, Memory, MemoryConfig, TrapKind, Vm};
+
+#[test]
+fn blocks_are_zeroed_and_reused() {
+    let mut mem = Memory::new(&[], MemoryConfig::default()).unwrap();
+    let mut heap = Heap::new();
+
+    let a = heap.alloc(&mut mem, 24).unwrap();
+    let b = heap.alloc(&mut mem, 24).unwrap();
+    assert_ne!(a, b);
+
+    mem.set::<u64>(a, 7).unwrap();
+    heap.free(&mut mem, a).unwrap();
+
+    assert_eq!(heap.alloc(&mut mem, 20).unwrap(), a);
+    assert_eq!(mem.get::<u64>(a).unwrap(), 0);
+}
+
+#[test]
+fn large_blocks_get_their_own_pages() {
+    let mut mem = Memory::new(&[], MemoryConfig::default()).unwrap();
+    let mut heap = Heap::new();
+
+    let addr = heap.alloc(&mut mem, 10000).unwrap();
+    mem.set::<u8>(addr + 9999, 1).unwrap();
+
+    heap.free(&mut mem, addr).unwrap();
+    assert_eq!(mem.get::<u8>(addr).unwrap_err().kind, TrapKind::PageNotExist);
+}
+
+#[test]
+fn bad_frees_trap() {
+    let mut mem = Memory::new(&[], MemoryConfig::default()).unwrap();
+    let mut heap = Heap::new();
+
+    let addr = heap.alloc(&mut mem, 8).unwrap();
+    heap.free(&mut mem, addr).unwrap();
+
+    assert_eq!(heap.free(&mut mem, addr).unwrap_err().kind, TrapKind::DoubleFree);
+    assert_eq!(heap.free(&mut mem, addr + 1).unwrap_err().kind, TrapKind::InvalidFree);
+}
+
+#[test]
+fn large_blocks_with_missing_pages_are_not_freed() {
+    let mut mem = Memory::new(&[], MemoryConfig::default()).unwrap();
+    let mut heap = Heap::new();
+
+    let addr = heap.alloc(&mut mem, 10000).unwrap();
+    mem.free_page(addr + 4096).unwrap();
+
+    assert_eq!(heap.free(&mut mem, addr).unwrap_err().kind, TrapKind::PageNotExist);
+    mem.set::<u8>(addr, 1).unwrap();
+    mem.set::<u8>(addr + 8192, 1).unwrap();
+    assert!(heap.owns(addr, 4096));
+}
+
+#[test]
+fn heap_pages_can_not_be_freed_as_pages() {
+    let src = "
+.start
+    alloc &1000 24
+    cpyw &1008 5000
+    alloc &1016 &1008
+    apg &1024
+    fpg &1024
+    ";
+
+    for tail in &["fpg &1000\n", "fpg &1016\n", "addw &1016 4096\n    fpg &1016\n"] {
+        let code = assemble(&format!("{}{}    xit\n", src, tail)).unwrap();
+        let trap = Vm::new(&code).run().unwrap_err();
+        assert_eq!(trap.kind, TrapKind::InvalidFree, "{}", tail);
+    }
+
+    // the pages of a freed large block go back to memory
+    let code = assemble(&format!("{}free &1016\n    fpg &1016\n    xit\n", src)).unwrap();
+    assert_eq!(Vm::new(&code).run().unwrap_err().kind, TrapKind::PageNotExist);
+}
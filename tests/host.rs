@@ -0,0 +1,51 @@
+#![allow(clippy::needless_return)]
+
+use mvm::{assemble, Fault, TrapKind, Vm};
+
+#[test]
+fn host_calls_pop_arguments_and_push_results() {
+    let src = "
+.start
+    pshw 20
+    pshw 22
+    sys 200
+    popw &1000
+    cpyb ._zero &1000
+    xit
+";
+    let mut vm = Vm::new(&assemble(src).unwrap());
+    vm.register_host(200, "add", Box::new(|mem| {
+        let b: u64 = mem.pop()?;
+        let a: u64 = mem.pop()?;
+        return Ok(a + b);
+    }));
+
+    assert_eq!(vm.host().number("add"), Some(200));
+    assert!(vm.host().calls().any(|call| call == (200, "add")));
+    assert_eq!(vm.run(), Ok(42));
+}
+
+#[test]
+fn unregistered_host_calls_trap() {
+    let src = ".start\n    sys 200\n    xit\n";
+    let trap = Vm::new(&assemble(src).unwrap()).run().unwrap_err();
+    assert_eq!(trap.kind, TrapKind::HostCall);
+    assert_eq!(trap.pc, 66);
+
+    let mut vm = Vm::new(&assemble(src).unwrap());
+    vm.register_host(200, "nothing", Box::new(|_| Ok(0)));
+    assert!(vm.host_mut().unregister(200).is_some());
+    assert_eq!(vm.run().unwrap_err().kind, TrapKind::HostCall);
+}
+
+#[test]
+fn host_faults_trap() {
+    let src = ".start\n    sys 200\n    xit\n";
+    let mut vm = Vm::new(&assemble(src).unwrap());
+    vm.register_host(200, "fail", Box::new(|_| Err(Fault::new(TrapKind::HostError, 1234))));
+
+    let trap = vm.run().unwrap_err();
+    assert_eq!(trap.kind, TrapKind::HostError);
+    assert_eq!(trap.pc, 66);
+    assert_eq!(trap.addr, Some(1234));
+}
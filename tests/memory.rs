@@ -0,0 +1,275 @@
+#![allow(clippy::needless_return)]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mvm::memory::Perm;
+use mvm::{assemble, Device, Fault, Memory, MemoryConfig, TrapKind, Vm};
+
+fn memory() -> Memory {
+    return Memory::new(&[], MemoryConfig::default()).unwrap();
+}
+
+#[test]
+fn page_values_read_back() {
+    let mut mem = memory();
+    let page = mem.alloc_page().unwrap();
+
+    mem.set::<u64>(page, 0x0123_4567_89ab_cdef).unwrap();
+    mem.set::<u8>(page + 8, 7).unwrap();
+    mem.set::<u32>(page + 4091, 0xdead_beef).unwrap(); // unaligned
+
+    assert_eq!(mem.get::<u64>(page).unwrap(), 0x0123_4567_89ab_cdef);
+    assert_eq!(mem.get::<u8>(page + 8).unwrap(), 7);
+    assert_eq!(mem.get::<u32>(page + 4091).unwrap(), 0xdead_beef);
+}
+
+#[test]
+fn pages_do_not_alias() {
+    let mut mem = memory();
+    let a = mem.alloc_page().unwrap();
+    let b = mem.alloc_page().unwrap();
+    assert_ne!(a, b);
+
+    mem.set::<u64>(a, 1).unwrap();
+    mem.set::<u64>(b, 2).unwrap();
+
+    assert_eq!(mem.get::<u64>(a).unwrap(), 1);
+    assert_eq!(mem.get::<u64>(b).unwrap(), 2);
+}
+
+#[test]
+fn bytes_are_written_in_place() {
+    let mut mem = memory();
+    let page = mem.alloc_page().unwrap();
+
+    mem.set_bytes(page + 100, &[1, 2, 3]).unwrap();
+    mem.fill(page + 103, 9, 2).unwrap();
+
+    assert_eq!(mem.bytes(page + 100, 5).unwrap(), &[1, 2, 3, 9, 9]);
+    assert_eq!(mem.get::<u8>(page + 101).unwrap(), 2);
+}
+
+#[test]
+fn ranges_span_pages() {
+    let mut mem = memory();
+    let a = mem.alloc_page().unwrap();
+    let b = mem.alloc_page().unwrap();
+    assert_eq!(b, a + 4096);
+
+    let data: Vec<u8> = (0..16).collect();
+    mem.write_range(b - 8, &data).unwrap();
+
+    assert_eq!(mem.read_range(b - 8, 16).unwrap(), data);
+    assert_eq!(mem.get::<u8>(b).unwrap(), 8);
+}
+
+#[test]
+fn access_past_page_end_faults() {
+    let mut mem = memory();
+    let page = mem.alloc_page().unwrap();
+
+    let fault = mem.set::<u64>(page + 4092, 1).unwrap_err();
+    assert_eq!(fault.kind, TrapKind::OutOfBounds);
+
+    let fault = mem.get::<u8>(page + 4096).unwrap_err();
+    assert_eq!(fault.kind, TrapKind::PageNotExist);
+}
+
+#[test]
+fn freed_pages_fault_and_come_back_zeroed() {
+    let mut mem = memory();
+    let page = mem.alloc_page().unwrap();
+    mem.set::<u64>(page, 42).unwrap();
+    mem.free_page(page).unwrap();
+
+    let fault = mem.get::<u64>(page).unwrap_err();
+    assert_eq!(fault.kind, TrapKind::PageNotExist);
+    assert_eq!(mem.free_page(page).unwrap_err().kind, TrapKind::PageNotExist);
+
+    assert_eq!(mem.alloc_page().unwrap(), page);
+    assert_eq!(mem.get::<u64>(page).unwrap(), 0);
+}
+
+#[test]
+fn page_size_and_limit_come_from_config() {
+    let config = MemoryConfig{ page_size: 256, max_pages: 2, ..Default::default() };
+    let mut mem = Memory::new(&[], config).unwrap();
+
+    let a = mem.alloc_page().unwrap();
+    let b = mem.alloc_page().unwrap();
+    assert_eq!(b, a + 256);
+    assert_eq!(mem.alloc_page().unwrap_err().kind, TrapKind::OutOfMemory);
+
+    mem.set::<u16>(b + 254, 0xabcd).unwrap();
+    assert_eq!(mem.get::<u16>(b + 254).unwrap(), 0xabcd);
+    assert_eq!(mem.get::<u16>(b + 255).unwrap_err().kind, TrapKind::OutOfBounds);
+}
+
+#[test]
+fn program_reads_back_page_writes() {
+    let src = "
+.start
+    apg &1000
+    cpyw [&1000]+16 40
+    addw [&1000]+16 2
+    cpyb ._zero [&1000]+16
+    xit
+";
+    let code = assemble(src).unwrap();
+    let mut vm = Vm::new(&code);
+
+    assert_eq!(vm.run(), Ok(42));
+
+    let page: usize = vm.read(1000).unwrap();
+    assert_eq!(vm.read::<u64>(page + 16), Ok(42));
+}
+
+#[test]
+fn frame_addresses_stay_on_the_stack() {
+    let mut mem = memory();
+    let page = mem.alloc_page().unwrap();
+    mem.set::<u8>(page, 99).unwrap();
+
+    // the stack is empty, so &1 is past its base rather than in page 0
+    assert_eq!(mem.get::<u8>(1).unwrap_err().kind, TrapKind::StackUnderflow);
+
+    mem.push::<u64>(7).unwrap();
+    assert_eq!(mem.get::<u64>(1).unwrap(), 7);
+    assert_eq!(mem.get::<u8>(8).unwrap(), 0);
+    assert_eq!(mem.get::<u16>(8).unwrap_err().kind, TrapKind::StackUnderflow);
+    assert_eq!(mem.read_range(1, 9).unwrap_err().kind, TrapKind::StackUnderflow);
+}
+
+/**
+ * reads back the offset of each byte
+ */
+struct Offsets;
+
+impl Device for Offsets {
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Fault> {
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = (offset + i) as u8;
+        }
+        return Ok(());
+    }
+
+    fn write(&mut self, _offset: usize, _bytes: &[u8]) -> Result<(), Fault> {
+        return Ok(());
+    }
+}
+
+#[test]
+fn devices_stay_clear_of_pages() {
+    let config = MemoryConfig{ page_size: 256, max_pages: 2, ..Default::default() };
+    let mut mem = Memory::new(&[], config).unwrap();
+
+    let a = mem.alloc_page().unwrap();
+    let end = a + 2 * 256;
+
+    assert!(mem.map_device(a, 16, Box::new(Offsets)).is_err());
+    assert!(mem.map_device(end - 8, 16, Box::new(Offsets)).is_err());
+    mem.map_device(end, 16, Box::new(Offsets)).unwrap();
+    assert!(mem.map_device(end + 8, 16, Box::new(Offsets)).is_err());
+
+    // the last page is still ordinary memory
+    let b = mem.alloc_page().unwrap();
+    mem.set::<u8>(b + 255, 7).unwrap();
+    assert_eq!(mem.get::<u8>(b + 255).unwrap(), 7);
+    assert_eq!(mem.get::<u8>(end + 3).unwrap(), 3);
+}
+
+type Writes = Rc<RefCell<Vec<(usize, Vec<u8>)>>>; // offset, bytes
+
+/**
+ * records the writes to its first 8 bytes and faults past them
+ */
+struct Recorder {
+    writes: Writes,
+}
+
+impl Device for Recorder {
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Fault> {
+        return Offsets.read(offset, buf);
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Fault> {
+        if offset + bytes.len() > 8 {
+            return Err(TrapKind::HostError.into());
+        }
+        self.writes.borrow_mut().push((offset, bytes.to_vec()));
+        return Ok(());
+    }
+}
+
+#[test]
+fn guest_accesses_reach_devices() {
+    let dev = 1usize << 40;
+    let src = format!("
+.start
+    cpyb &{} 5
+    cpyh &{} 258
+    cpyw &1000 &{}
+    cpyb &{} 1
+    xit
+", dev, dev + 2, dev, dev + 8);
+
+    let writes: Writes = Rc::new(RefCell::new(vec![]));
+    let mut vm = Vm::new(&assemble(&src).unwrap());
+    vm.map_device(dev, 16, Box::new(Recorder{ writes: writes.clone() })).unwrap();
+
+    let trap = vm.run().unwrap_err();
+    assert_eq!(trap.kind, TrapKind::HostError);
+    assert_eq!(trap.addr, Some(dev + 8));
+
+    assert_eq!(*writes.borrow(), [(0, vec![5]), (2, vec![2, 1])]);
+    assert_eq!(vm.read::<u64>(1000), Ok(0x0706_0504_0302_0100));
+}
+
+#[test]
+fn overlapping_protects_split_regions() {
+    let mut mem = memory();
+    let page = mem.alloc_page().unwrap();
+
+    mem.protect(page, 100, Perm::READ);
+    mem.protect(page + 40, 20, Perm::RWX);
+    mem.protect(page + 90, 20, Perm::NONE);
+
+    assert_eq!(mem.perm(page + 39), Perm::READ);
+    assert_eq!(mem.perm(page + 40), Perm::RWX);
+    assert_eq!(mem.perm(page + 59), Perm::RWX);
+    assert_eq!(mem.perm(page + 60), Perm::READ);
+    assert_eq!(mem.perm(page + 90), Perm::NONE);
+    assert_eq!(mem.perm(page + 109), Perm::NONE);
+    assert_eq!(mem.perm(page + 110), Perm::DEFAULT);
+
+    assert_eq!(mem.set::<u8>(page, 1).unwrap_err().kind, TrapKind::Protection);
+    mem.set::<u64>(page + 44, 1).unwrap();
+    assert_eq!(mem.set::<u64>(page + 56, 1).unwrap_err().kind, TrapKind::Protection);
+    assert_eq!(mem.get::<u8>(page + 100).unwrap_err().kind, TrapKind::Protection);
+    assert_eq!(mem.fetch(page + 50).unwrap(), 0);
+    assert_eq!(mem.fetch(page).unwrap_err().kind, TrapKind::Protection);
+}
+
+#[test]
+fn jumping_into_data_traps() {
+    let src = "
+.start
+    cpyw &1008 1000
+    jmp &1008
+";
+    let code = assemble(src).unwrap();
+    let trap = Vm::new(&code).run().unwrap_err();
+    assert_eq!(trap.kind, TrapKind::Protection);
+    assert_eq!(trap.pc, 1000);
+}
+
+#[test]
+fn huge_stack_guards_stop_at_fast_memory() {
+    let mut mem = memory();
+    mem.set_stack_guard(usize::MAX);
+    assert_eq!(mem.push::<u8>(1).unwrap_err().kind, TrapKind::StackOverflow);
+
+    mem.set_stack_guard(0);
+    mem.push::<u8>(1).unwrap();
+}
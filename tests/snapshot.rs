@@ -0,0 +1,234 @@
+#![allow(clippy::needless_return)]
+
+use std::convert::TryFrom;
+use std::io;
+use std::io::Write;
+
+use mvm::memory::Perm;
+use mvm::{assemble, Device, Fault, MemoryConfig, State, TrapKind, Vm};
+
+const SRC: &str = "
+.start
+    alloc &1000 40
+    apg &1016
+    cpyw &1008 100
+.loop
+    addw [&1000]+8 3
+    addw [&1016] 1
+    subw &1008 1
+    jnz .loop
+    cpyb ._zero [&1000]+8
+    addb ._zero [&1016]
+    free &1000
+    xit
+";
+
+fn vm() -> Vm {
+    return Vm::new(&assemble(SRC).unwrap());
+}
+
+#[test]
+fn restored_vm_finishes_like_the_original() {
+    let mut expected = vm();
+    let status = expected.run().unwrap();
+
+    let mut vm = vm();
+    vm.set_fuel(300);
+    assert_eq!(vm.run().unwrap_err().kind, TrapKind::OutOfFuel);
+
+    let mut snap = vec![];
+    vm.snapshot(&mut snap).unwrap();
+
+    let mut restored = Vm::new(&[]);
+    restored.restore(snap.as_slice()).unwrap();
+    assert_eq!(restored.pc(), vm.pc());
+    assert_eq!(restored.sp(), vm.sp());
+    assert_eq!(restored.fuel(), vm.fuel());
+    assert_eq!(restored.state(), vm.state());
+
+    restored.unlimited_fuel();
+    assert_eq!(restored.run(), Ok(status));
+}
+
+#[test]
+fn snapshots_of_restored_vms_match() {
+    let mut vm = vm();
+    for _ in 0..50 {
+        vm.step();
+    }
+
+    let mut snap = vec![];
+    vm.snapshot(&mut snap).unwrap();
+
+    let config = MemoryConfig{ fast_size: 1024, ..Default::default() };
+    let mut restored = Vm::with_config(&[], config).unwrap();
+    restored.restore(snap.as_slice()).unwrap();
+
+    let mut again = vec![];
+    restored.snapshot(&mut again).unwrap();
+    assert_eq!(snap, again);
+}
+
+#[test]
+fn exited_state_is_kept() {
+    let mut vm = vm();
+    let status = vm.run().unwrap();
+
+    let mut snap = vec![];
+    vm.snapshot(&mut snap).unwrap();
+
+    let mut restored = Vm::new(&[]);
+    restored.restore(snap.as_slice()).unwrap();
+    assert_eq!(restored.state(), State::Exited(status));
+}
+
+#[test]
+fn bad_snapshots_are_rejected() {
+    let mut vm = vm();
+    let mut snap = vec![];
+    vm.snapshot(&mut snap).unwrap();
+
+    let err = vm.restore(&b"not a snapshot"[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let mut version = snap.clone();
+    version[4] = 99;
+    let err = vm.restore(version.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let err = vm.restore(&snap[..snap.len() - 1]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    // a failed restore leaves the vm as it was
+    assert_eq!(vm.pc(), 66);
+    assert!(vm.run().is_ok());
+}
+
+/**
+ * a snapshot of SRC run with small pages, and where its memory config
+ * (fast_size, page_size, frame_size, max_pages) starts
+ */
+fn small_snapshot() -> (Vec<u8>, usize) {
+    let config = MemoryConfig{ fast_size: 1024, page_size: 256, max_pages: 3,
+                               ..Default::default() };
+    let mut vm = Vm::with_config(&assemble(SRC).unwrap(), config).unwrap();
+    vm.run_until(10);
+
+    let mut snap = vec![];
+    vm.snapshot(&mut snap).unwrap();
+
+    let mut fields = vec![];
+    for &n in &[1024u64, 256, 64, 3] {
+        fields.extend_from_slice(&n.to_le_bytes());
+    }
+    let at = snap.windows(fields.len()).position(|w| w == fields.as_slice()).unwrap();
+
+    return (snap, at);
+}
+
+#[test]
+fn snapshots_past_their_limits_are_rejected() {
+    let (snap, config) = small_snapshot();
+    let max_pages = config + 24;
+    let guard = config + 48;
+
+    let mut vm = Vm::new(&[]);
+    vm.restore(snap.as_slice()).unwrap();
+
+    // the heap and apg hold two pages
+    let mut pages = snap.clone();
+    pages[max_pages..max_pages + 8].copy_from_slice(&1u64.to_le_bytes());
+    let err = vm.restore(pages.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    for &len in &[1024u64, u64::MAX] {
+        let mut guarded = snap.clone();
+        guarded[guard..guard + 8].copy_from_slice(&len.to_le_bytes());
+        let err = vm.restore(guarded.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn trap_kinds_round_trip() {
+    let mut n = 0;
+    for i in 0..=255 {
+        if let Ok(kind) = TrapKind::try_from(i) {
+            assert_eq!(kind as u8, i);
+            n += 1;
+        }
+    }
+    assert_eq!(n, TrapKind::InvalidFree as u8 + 1);
+}
+
+struct Nothing;
+
+impl Device for Nothing {
+    fn read(&mut self, _offset: usize, buf: &mut [u8]) -> Result<(), Fault> {
+        buf.iter_mut().for_each(|b| *b = 0);
+        return Ok(());
+    }
+
+    fn write(&mut self, _offset: usize, _bytes: &[u8]) -> Result<(), Fault> {
+        return Ok(());
+    }
+}
+
+#[test]
+fn snapshots_overlapping_devices_are_rejected() {
+    let (snap, config) = small_snapshot();
+    let max_pages = config + 24;
+
+    let mut vm = Vm::new(&[]);
+    vm.map_device(1 << 50, 16, Box::new(Nothing)).unwrap();
+    vm.restore(snap.as_slice()).unwrap();
+
+    let mut fast = snap.clone();
+    fast[config..config + 8].copy_from_slice(&(u64::MAX - 10).to_le_bytes());
+    let err = vm.restore(fast.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let mut pages = snap.clone();
+    pages[max_pages..max_pages + 8].copy_from_slice(&(1u64 << 45).to_le_bytes());
+    let err = vm.restore(pages.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn overlapping_regions_are_rejected() {
+    let mut vm = vm();
+    vm.memory_mut().protect(2000, 100, Perm::READ);
+    vm.memory_mut().protect(3000, 100, Perm::READ);
+
+    let mut snap = vec![];
+    vm.snapshot(&mut snap).unwrap();
+    Vm::new(&[]).restore(snap.as_slice()).unwrap();
+
+    // regions come last, as start end perm
+    let last = snap.len() - 17;
+    snap[last..last + 8].copy_from_slice(&2050u64.to_le_bytes());
+    let err = Vm::new(&[]).restore(snap.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+/**
+ * takes writes but can't flush them
+ */
+struct Unflushable;
+
+impl Write for Unflushable {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Err(io::ErrorKind::BrokenPipe.into());
+    }
+}
+
+#[test]
+fn snapshots_are_flushed() {
+    let vm = vm();
+    let err = vm.snapshot(io::BufWriter::new(Unflushable)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}
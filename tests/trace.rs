@@ -0,0 +1,83 @@
+#![allow(clippy::needless_return)]
+
+use mvm::console::Capture;
+use mvm::op_code::Op;
+use mvm::trace::{JsonTracer, TextTracer};
+use mvm::{assemble, Tracer, TrapKind, Vm};
+
+const SRC: &str = "
+.start
+    cpyb &1000 7
+    addb &1000 &1000
+    divb &1000 0
+    xit
+";
+
+/**
+ * the lines traced running SRC
+ */
+fn trace<F: Fn(Capture) -> Box<dyn Tracer>>(tracer: F) -> Vec<String> {
+    let out = Capture::new();
+    let mut vm = Vm::new(&assemble(SRC).unwrap());
+    vm.set_tracer(tracer(out.clone()));
+    assert_eq!(vm.run().unwrap_err().kind, TrapKind::DivideByZero);
+
+    let text = String::from_utf8(out.contents()).unwrap();
+    return text.lines().map(str::to_string).collect();
+}
+
+#[test]
+fn text_trace() {
+    let lines = trace(|out| Box::new(TextTracer::new(out)));
+
+    assert_eq!(lines, [
+        "      66 CPY1",
+        "           write 1000 [07]",
+        "      76 ADD2",
+        "           read  1000 [07]",
+        "           read  1000 [07]",
+        "           write 1000 [0e]",
+        "           write 65 [00]",
+        "      93 DIV1",
+        "           read  1000 [0e]",
+        "trap: divide by zero at pc 93 (DIV1)",
+    ]);
+}
+
+#[test]
+fn json_trace() {
+    let lines = trace(|out| Box::new(JsonTracer::new(out)));
+
+    let fetch = |pc: usize, op: Op| {
+        return format!(r#"{{"event":"fetch","pc":{},"op":{},"name":"{}"}}"#, pc, op as u8, op);
+    };
+    let trap = format!(r#"{{"event":"trap","kind":"DivideByZero","pc":93,"op":{},"addr":null}}"#,
+                       Op::DIV1 as u8);
+
+    assert_eq!(lines, [
+        fetch(66, Op::CPY1),
+        r#"{"event":"write","addr":1000,"bytes":[7]}"#.to_string(),
+        fetch(76, Op::ADD2),
+        r#"{"event":"read","addr":1000,"bytes":[7]}"#.to_string(),
+        r#"{"event":"read","addr":1000,"bytes":[7]}"#.to_string(),
+        r#"{"event":"write","addr":1000,"bytes":[14]}"#.to_string(),
+        r#"{"event":"write","addr":65,"bytes":[0]}"#.to_string(),
+        fetch(93, Op::DIV1),
+        r#"{"event":"read","addr":1000,"bytes":[14]}"#.to_string(),
+        trap,
+    ]);
+
+    // each line is a single object, closed only at its end
+    for line in &lines {
+        let mut depth = 0;
+        for (i, c) in line.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {},
+            }
+            assert!(depth > 0 || i == line.len() - 1, "{}", line);
+        }
+        assert_eq!(depth, 0, "{}", line);
+    }
+}
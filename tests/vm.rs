@@ -0,0 +1,417 @@
+#![allow(clippy::needless_return)]
+
+use mvm::alu::{CARRY, NEGATIVE, ZERO};
+use mvm::asm::assemble_with_labels;
+use mvm::memory::FLAGS_ADDR;
+use mvm::op_code::{Op, OpExt};
+use mvm::{assemble, State, TrapKind, Vm};
+
+#[test]
+fn huge_comment_traps() {
+    let mut code = vec![Op::EXT as u8, OpExt::CMT as u8];
+    code.extend_from_slice(&u64::MAX.to_le_bytes());
+
+    let trap = Vm::new(&code).run().unwrap_err();
+    assert_eq!(trap.kind, TrapKind::OutOfBounds);
+}
+
+#[test]
+fn sleep_costs_fuel_for_each_millisecond() {
+    let mut vm = Vm::new(&assemble(".start\n    slp 18446744073709551615\n    xit\n").unwrap());
+    vm.set_fuel(1000);
+    assert_eq!(vm.run().unwrap_err().kind, TrapKind::OutOfFuel);
+
+    let mut vm = Vm::new(&assemble(".start\n    slp 5\n    xit\n").unwrap());
+    vm.set_fuel(100);
+    assert_eq!(vm.run(), Ok(0));
+    assert!(vm.fuel().unwrap() <= 95);
+}
+
+#[test]
+fn out_of_fuel_resumes_after_adding_fuel() {
+    let src = "
+.start
+    cpyb &1000 0
+.loop
+    addb &1000 1
+    cpyb &1001 &1000
+    subb &1001 10
+    jnz .loop
+    cpyb ._zero &1000
+    xit
+";
+    let mut vm = Vm::new(&assemble(src).unwrap());
+    vm.set_fuel(10);
+
+    let trap = vm.run().unwrap_err();
+    assert_eq!(trap.kind, TrapKind::OutOfFuel);
+    assert_eq!(vm.pc(), trap.pc);
+    let count: u8 = vm.read(1000).unwrap();
+    assert!(count > 0 && count < 10);
+
+    vm.add_fuel(1000);
+    assert_eq!(vm.state(), State::Running);
+    assert_eq!(vm.run(), Ok(10));
+}
+
+#[test]
+fn adding_fuel_keeps_unlimited_vms_unlimited() {
+    let mut vm = Vm::new(&assemble(".start\n    nop\n    nop\n    nop\n    xit\n").unwrap());
+    vm.add_fuel(1);
+    assert_eq!(vm.fuel(), None);
+    assert_eq!(vm.run(), Ok(0));
+}
+
+/**
+ * assemble and run src, which must exit cleanly
+ */
+fn run(src: &str) -> Vm {
+    let mut vm = Vm::new(&assemble(src).unwrap());
+    assert_eq!(vm.run(), Ok(0));
+    return vm;
+}
+
+#[test]
+fn signed_division_and_shifts() {
+    let vm = run("
+.start
+    cpyb &1000 -7
+    divsb &1000 2
+    cpyb &1001 -7
+    modsb &1001 2
+    cpyb &1002 -128
+    sarb &1002 3
+    cpyb &1003 -128
+    divb &1003 2
+    cpyw &1010 -7
+    divsw &1010 2
+    cpyw &1018 -7
+    modsw &1018 2
+    cpyw &1026 -64
+    sarw &1026 70
+    cpyw &1034 -9223372036854775808
+    divsw &1034 -1
+    jnv .bad
+    xit
+.bad
+    cpyb ._zero 99
+    xit
+");
+
+    assert_eq!(vm.read::<i8>(1000), Ok(-3));
+    assert_eq!(vm.read::<i8>(1001), Ok(-1));
+    assert_eq!(vm.read::<i8>(1002), Ok(-16));
+    assert_eq!(vm.read::<u8>(1003), Ok(64));
+    assert_eq!(vm.read::<i64>(1010), Ok(-3));
+    assert_eq!(vm.read::<i64>(1018), Ok(-1));
+    assert_eq!(vm.read::<i64>(1026), Ok(-1));
+    assert_eq!(vm.read::<i64>(1034), Ok(i64::MIN));
+}
+
+#[test]
+fn signed_division_by_zero_traps() {
+    let code = assemble(".start\n    cpyw &1000 -1\n    modsw &1000 0\n    xit\n").unwrap();
+    assert_eq!(Vm::new(&code).run().unwrap_err().kind, TrapKind::DivideByZero);
+}
+
+#[test]
+fn half_and_double_widths() {
+    let vm = run("
+.start
+    cpyh &1000 65535
+    addh &1000 2
+    jnc .bad
+    cpyh &1002 -300
+    mulh &1002 2
+    cpyd &1004 2147483647
+    addd &1004 1
+    jnv .bad
+    cpyd &1008 -100
+    sard &1008 2
+    andh &1002 255
+    pshh 300
+    pshd -5
+    popd &1012
+    poph &1016
+    cpyh &1018 &1016
+    xit
+.bad
+    cpyb ._zero 99
+    xit
+");
+
+    assert_eq!(vm.read::<u16>(1000), Ok(1));
+    assert_eq!(vm.read::<i16>(1002), Ok(-600 & 255));
+    assert_eq!(vm.read::<i32>(1004), Ok(i32::MIN));
+    assert_eq!(vm.read::<i32>(1008), Ok(-25));
+    assert_eq!(vm.read::<i32>(1012), Ok(-5));
+    assert_eq!(vm.read::<u16>(1016), Ok(300));
+    assert_eq!(vm.read::<u16>(1018), Ok(300));
+    // the neighbouring bytes are untouched
+    assert_eq!(vm.read::<u8>(1020), Ok(0));
+}
+
+#[test]
+fn float_arithmetic_and_conversions() {
+    let vm = run("
+.start
+    cpyd &1000 0
+    addf &1000 1.5
+    mulf &1000 -3
+    cpyw &1008 0
+    addfw &1008 10
+    divfw &1008 4
+    cpyw &1016 0
+    subfw &1016 &1008
+    cmpfw &1016 &1008
+    jnc .bad
+    cpyw &1024 -7
+    itfw &1032 &1024
+    itf &1040 &1024
+    trnfw &1048 &1016
+    rndfw &1056 &1016
+    trnf &1064 &1000
+    rndf &1072 &1000
+    extf &1080 &1000
+    nrwf &1088 &1008
+    cpyw &1096 0
+    divfw &1096 0
+    cmpfw &1096 &1096
+    jnv .bad
+    xit
+.bad
+    cpyb ._zero 99
+    xit
+");
+
+    assert_eq!(vm.read::<f32>(1000), Ok(-4.5));
+    assert_eq!(vm.read::<f64>(1008), Ok(2.5));
+    assert_eq!(vm.read::<f64>(1016), Ok(-2.5));
+    assert_eq!(vm.read::<f64>(1032), Ok(-7.0));
+    assert_eq!(vm.read::<f32>(1040), Ok(-7.0));
+    assert_eq!(vm.read::<i64>(1048), Ok(-2));
+    assert_eq!(vm.read::<i64>(1056), Ok(-3));
+    assert_eq!(vm.read::<i64>(1064), Ok(-4));
+    assert_eq!(vm.read::<i64>(1072), Ok(-5));
+    assert_eq!(vm.read::<f64>(1080), Ok(-4.5));
+    assert_eq!(vm.read::<f32>(1088), Ok(2.5));
+    assert!(vm.read::<f64>(1096).unwrap().is_nan());
+}
+
+#[test]
+fn comparisons_write_booleans() {
+    let vm = run("
+.start
+    cpyb &1000 200
+    cpyw &1008 -5
+    cpyh &1016 -1
+    cpyd &1018 7
+    cpyd &1022 0
+    addf &1022 2.5
+    cpyw &1026 0
+    subfw &1026 1
+    eqb &1100 &1000 200
+    neb &1101 &1000 200
+    ltb &1102 &1000 100
+    ltsb &1103 &1000 100
+    gew &1104 &1008 0
+    gesw &1105 &1008 -5
+    leh &1106 &1016 0
+    lesh &1107 &1016 0
+    gtd &1108 &1018 &1018
+    gtsd &1109 &1018 6
+    lef &1110 &1022 2.5
+    gtfw &1111 &1026 -2
+    nefw &1112 &1026 &1026
+    ltsb &1120 &1000 0
+    jit .skip &1120
+    cpyb ._zero 99
+.skip
+    xit
+");
+
+    let got: Vec<u8> = (1100..1113).map(|addr| vm.read(addr).unwrap()).collect();
+    assert_eq!(got, [1, 0, 0, 1, 1, 1, 0, 1, 0, 1, 1, 1, 0]);
+}
+
+#[test]
+fn float_comparisons_with_nan_are_false() {
+    let vm = run("
+.start
+    cpyw &1000 0
+    divfw &1000 0
+    eqfw &1010 &1000 &1000
+    ltfw &1011 &1000 1
+    gefw &1012 &1000 1
+    nefw &1013 &1000 &1000
+    xit
+");
+
+    let got: Vec<u8> = (1010..1014).map(|addr| vm.read(addr).unwrap()).collect();
+    assert_eq!(got, [0, 0, 0, 1]);
+}
+
+#[test]
+fn overlapping_copies() {
+    let vm = run("
+.start
+    cpyw &1000 578437695752307201
+    cpyw &1100 578437695752307201
+    mcpy &1002 &1000 6
+    mcpy &1100 &1102 6
+    xit
+");
+
+    // 578437695752307201 is the bytes 1 to 8
+    assert_eq!(vm.memory().read_range(1000, 8).unwrap(), [1, 2, 1, 2, 3, 4, 5, 6]);
+    assert_eq!(vm.memory().read_range(1100, 8).unwrap(), [3, 4, 5, 6, 7, 8, 7, 8]);
+}
+
+#[test]
+fn fill_with_stored_length() {
+    let vm = run("
+.start
+    cpyw &1000 5
+    mset &1010 171 &1000
+    xit
+");
+
+    assert_eq!(vm.memory().read_range(1009, 7).unwrap(), [0, 171, 171, 171, 171, 171, 0]);
+}
+
+#[test]
+fn block_compare_flags() {
+    let src = "
+.start
+    cpyw &1000 578437695752307201
+    cpyw &1008 578437695752307201
+    cpyb &1012 {}
+    mcmp &1000 &1008 8
+    xit
+";
+
+    let flags = |b: u8| {
+        let vm = run(&src.replace("{}", &b.to_string()));
+        return vm.read::<u8>(FLAGS_ADDR).unwrap();
+    };
+
+    assert_eq!(flags(5), ZERO);
+    assert_eq!(flags(9), CARRY | NEGATIVE); // a is less
+    assert_eq!(flags(1), 0);
+}
+
+#[test]
+fn block_ops_into_missing_pages_trap() {
+    for op in &["mcpy [&1000]+4090 &2000 16", "mset [&1000]+4090 1 16",
+                "mcmp &2000 [&1000]+4090 16", "mcpy &2000 [&1000]+4090 16"] {
+        let src = format!(".start\n    apg &1000\n    {}\n    xit\n", op);
+        let trap = Vm::new(&assemble(&src).unwrap()).run().unwrap_err();
+        assert_eq!(trap.kind, TrapKind::PageNotExist, "{}", op);
+    }
+}
+
+#[test]
+fn stepping_shows_intermediate_state() {
+    let src = "
+.start
+    pshb 7
+    pshw 1000
+    popw &1000
+    cpyb ._zero &1
+    xit
+";
+    let mut vm = Vm::new(&assemble(src).unwrap());
+    let sp = vm.sp();
+    assert_eq!((vm.pc(), vm.state()), (66, State::Running));
+
+    let expected = [
+        (68, sp - 1),
+        (77, sp - 9),
+        (86, sp - 1),
+        (103, sp - 1),
+    ];
+    for &(pc, sp) in &expected {
+        assert_eq!(vm.step(), State::Running);
+        assert_eq!((vm.pc(), vm.sp()), (pc, sp));
+    }
+    assert_eq!(vm.read::<u64>(1000), Ok(1000));
+
+    assert_eq!(vm.step(), State::Exited(7));
+    let pc = vm.pc();
+    assert_eq!(vm.step(), State::Exited(7));
+    assert_eq!(vm.run_until(10), State::Exited(7));
+    assert_eq!((vm.pc(), vm.state()), (pc, State::Exited(7)));
+}
+
+#[test]
+fn traps_stay_in_place() {
+    let src = "
+.start
+    cpyb &1000 1
+    popw &1008
+    xit
+";
+    let mut vm = Vm::new(&assemble(src).unwrap());
+
+    assert_eq!(vm.run_until(1), State::Running);
+    let state = vm.run_until(10);
+    let trap = match state {
+        State::Trapped(trap) => trap,
+        state => panic!("{:?}", state),
+    };
+    assert_eq!(trap.kind, TrapKind::StackUnderflow);
+    assert_eq!(vm.pc(), trap.pc);
+
+    assert_eq!(vm.step(), state);
+    assert_eq!(vm.run(), Err(trap));
+    assert_eq!((vm.pc(), vm.state()), (trap.pc, state));
+
+    // moving pc doesn't resume a trapped program
+    vm.set_pc(66);
+    assert_eq!(vm.step(), state);
+    assert_eq!(vm.read::<u8>(1000), Ok(1));
+}
+
+#[test]
+fn jumps_calls_and_returns() {
+    let src = "
+.start
+    cpyw &1008 1000
+.loop
+    cal .outer
+    cpyb &1017 &1016
+    subb &1017 5
+    jnz .loop
+    jmp .skip
+    cpyb ._zero 99
+    xit
+.skip
+    jmp [&1008]
+    cpyb ._zero 98
+    xit
+.done
+    cpyb ._zero &1024
+    xit
+.outer
+    addb &1016 1
+    cal .inner
+    ret
+.inner
+    addb &1024 2
+    ret
+";
+    let (code, labels) = assemble_with_labels(src).unwrap();
+    let mut vm = Vm::new(&code);
+    vm.write::<u64>(1000, labels[".done"] as u64).unwrap();
+
+    let sp = vm.sp();
+    assert_eq!(vm.run(), Ok(10));
+    assert_eq!(vm.sp(), sp);
+}
+
+#[test]
+fn return_on_an_empty_stack_traps() {
+    let trap = Vm::new(&assemble(".start\n    ret\n").unwrap()).run().unwrap_err();
+    assert_eq!(trap.kind, TrapKind::StackUnderflow);
+    assert_eq!(trap.pc, 66);
+}